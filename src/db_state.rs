@@ -0,0 +1,478 @@
+use crate::error::ReadStateError;
+use oxrdf::{
+    dataset::CanonicalizationAlgorithm, vocab::xsd, BlankNode, Dataset, Graph, Literal, Quad, Term, Triple, Variable,
+};
+use oxsdatatypes::{Boolean, Decimal, Double, Float, Integer};
+use oxttl::{NQuadsParser, NTriplesParser, TriGParser, TurtleSyntaxError};
+use sparesults::{
+    QueryResultsFormat, QueryResultsParser, QueryResultsSerializer, QuerySolution, SliceQueryResultsParserOutput,
+};
+use spargebra::{algebra::GraphPattern, SparqlParser};
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+/// The kind of result a validation query returns, which determines how its state is read and compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateForm {
+    /// CONSTRUCT and DESCRIBE queries
+    Graph,
+    /// Quads of several graphs
+    Dataset,
+    /// A named graph read from the graph store endpoint, which may not exist
+    GraphStore,
+    /// SELECT queries. The order of the solutions only matters if the query has an ORDER BY clause.
+    Solutions { ordered: bool },
+    /// ASK queries
+    Boolean,
+}
+
+impl StateForm {
+    pub fn of_query(query: &str) -> Result<Self, spargebra::SparqlSyntaxError> {
+        Ok(match SparqlParser::new().parse_query(query)? {
+            spargebra::Query::Construct { .. } | spargebra::Query::Describe { .. } => StateForm::Graph,
+            spargebra::Query::Select { pattern, .. } => StateForm::Solutions { ordered: is_ordered(&pattern) },
+            spargebra::Query::Ask { .. } => StateForm::Boolean,
+        })
+    }
+
+    /// The value of the `Accept` header when reading a state of this form
+    pub fn accept(self) -> &'static str {
+        match self {
+            StateForm::Graph | StateForm::GraphStore => "application/n-triples",
+            StateForm::Dataset => "application/n-quads, application/trig;q=0.9",
+            StateForm::Solutions { .. } => {
+                "application/sparql-results+json, application/sparql-results+xml;q=0.9, text/tab-separated-values;q=0.8"
+            },
+            StateForm::Boolean => "application/sparql-results+json, application/sparql-results+xml;q=0.9",
+        }
+    }
+}
+
+/// Whether the solution sequence of a SELECT query is ordered, i.e. ORDER BY is applied before
+/// the projection and solution modifiers
+fn is_ordered(pattern: &GraphPattern) -> bool {
+    match pattern {
+        GraphPattern::OrderBy { .. } => true,
+        GraphPattern::Slice { inner, .. }
+        | GraphPattern::Distinct { inner }
+        | GraphPattern::Reduced { inner }
+        | GraphPattern::Project { inner, .. } => is_ordered(inner),
+        _ => false,
+    }
+}
+
+/// The state of (a part of) the database as returned by a validation query.
+///
+/// Graphs and datasets are compared as RDF graphs (datasets), i.e. blank nodes are compared up to isomorphism
+/// and literals of the common numeric and boolean XSD datatypes are compared by value.
+/// Solutions are compared as a multiset, unless the query is ordered. Their literals are compared
+/// by value as well, but blank nodes are not distinguished from each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DbState {
+    Graph(Graph),
+    Dataset(Dataset),
+    Solutions(Solutions),
+    Boolean(bool),
+    /// A named graph that does not exist in the graph store
+    Absent,
+}
+
+/// The solutions of a SELECT query, with the variables in lexicographic order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solutions {
+    variables: Vec<Variable>,
+    rows: Vec<Vec<Option<Term>>>,
+    ordered: bool,
+}
+
+impl DbState {
+    /// Parses a state from its N-Triples serialization
+    pub fn parse(ntriples: &str) -> Result<Self, TurtleSyntaxError> {
+        let triples: Result<Vec<Triple>, _> = NTriplesParser::new().for_slice(ntriples).collect();
+        Ok(triples?.into_iter().collect())
+    }
+
+    /// Parses a dataset from its N-Quads serialization
+    pub fn parse_dataset(nquads: &str) -> Result<Self, TurtleSyntaxError> {
+        let quads: Result<Vec<Quad>, _> = NQuadsParser::new().for_slice(nquads).collect();
+        Ok(quads?.into_iter().collect())
+    }
+
+    /// Parses the response to a validation query. Graphs are always parsed as N-Triples, datasets as TriG if
+    /// the content type says so and as N-Quads otherwise, and query results in the format given by the
+    /// content type (SPARQL JSON results if there is none).
+    pub fn parse_response(body: &[u8], content_type: Option<&str>, form: StateForm) -> Result<Self, ReadStateError> {
+        let ordered = match form {
+            StateForm::Graph | StateForm::GraphStore => return Ok(Self::parse(&String::from_utf8_lossy(body))?),
+            StateForm::Dataset if content_type.is_some_and(|ct| ct.starts_with("application/trig")) => {
+                let quads: Result<Vec<Quad>, _> = TriGParser::new().for_slice(body).collect();
+                return Ok(quads?.into_iter().collect());
+            },
+            StateForm::Dataset => return Ok(Self::parse_dataset(&String::from_utf8_lossy(body))?),
+            StateForm::Solutions { ordered } => ordered,
+            StateForm::Boolean => false,
+        };
+
+        // CSV results cannot be parsed, as they do not distinguish IRIs from literals
+        let format = match content_type.map(|ct| (ct, QueryResultsFormat::from_media_type(ct))) {
+            None => QueryResultsFormat::Json,
+            Some((_, Some(format))) if format != QueryResultsFormat::Csv => format,
+            Some((content_type, _)) => return Err(ReadStateError::UnsupportedFormat(content_type.to_owned())),
+        };
+
+        match (QueryResultsParser::from_format(format).for_slice(body)?, form) {
+            (SliceQueryResultsParserOutput::Solutions(solutions), StateForm::Solutions { .. }) => {
+                let variables = solutions.variables().to_vec();
+                let solutions = solutions.collect::<Result<Vec<_>, _>>()?;
+                Ok(DbState::Solutions(Solutions::new(variables, solutions, ordered)))
+            },
+            (SliceQueryResultsParserOutput::Boolean(value), StateForm::Boolean) => Ok(DbState::Boolean(value)),
+            (SliceQueryResultsParserOutput::Solutions(_), _) => Err(ReadStateError::UnexpectedResultForm("boolean")),
+            (SliceQueryResultsParserOutput::Boolean(_), _) => Err(ReadStateError::UnexpectedResultForm("solutions")),
+        }
+    }
+
+    /// Reads an expected state as written in a workload: graphs (datasets) as an N-Triples (N-Quads) string,
+    /// solutions as a SPARQL JSON results object, booleans as a JSON boolean and absent graphs as `null`
+    pub fn from_json(value: serde_json::Value, form: StateForm) -> anyhow::Result<Self> {
+        match (value, form) {
+            (serde_json::Value::String(ntriples), StateForm::Graph | StateForm::GraphStore) => {
+                Ok(Self::parse(&ntriples)?)
+            },
+            (serde_json::Value::Null, StateForm::GraphStore) => Ok(DbState::Absent),
+            (serde_json::Value::String(nquads), StateForm::Dataset) => Ok(Self::parse_dataset(&nquads)?),
+            (serde_json::Value::Bool(value), StateForm::Boolean) => Ok(DbState::Boolean(value)),
+            (value @ serde_json::Value::Object(_), form @ StateForm::Solutions { .. }) => {
+                Ok(Self::parse_response(&serde_json::to_vec(&value)?, None, form)?)
+            },
+            (_, StateForm::Graph) => {
+                anyhow::bail!("The expected state of a CONSTRUCT query must be an N-Triples string")
+            },
+            (_, StateForm::Dataset) => anyhow::bail!("The expected state of a dataset must be an N-Quads string"),
+            (_, StateForm::GraphStore) => {
+                anyhow::bail!("The expected state of a graph store graph must be an N-Triples string or null")
+            },
+            (_, StateForm::Solutions { .. }) => {
+                anyhow::bail!("The expected state of a SELECT query must be a SPARQL JSON results object")
+            },
+            (_, StateForm::Boolean) => anyhow::bail!("The expected state of an ASK query must be a boolean"),
+        }
+    }
+
+    /// The value of a SELECT query that returns a single solution with a single integer, e.g. a `COUNT`
+    pub fn as_integer(&self) -> Option<i64> {
+        let DbState::Solutions(Solutions { rows, .. }) = self else {
+            return None;
+        };
+
+        match rows.as_slice() {
+            [row] => match row.as_slice() {
+                [Some(Term::Literal(value))] => value.value().parse().ok(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn form(&self) -> StateForm {
+        match self {
+            DbState::Graph(_) => StateForm::Graph,
+            DbState::Dataset(_) => StateForm::Dataset,
+            DbState::Solutions(solutions) => StateForm::Solutions { ordered: solutions.ordered },
+            DbState::Boolean(_) => StateForm::Boolean,
+            DbState::Absent => StateForm::GraphStore,
+        }
+    }
+}
+
+impl Solutions {
+    fn new(mut variables: Vec<Variable>, solutions: Vec<QuerySolution>, ordered: bool) -> Self {
+        variables.sort_unstable_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        let mut rows: Vec<Vec<Option<Term>>> = solutions
+            .iter()
+            .map(|solution| {
+                variables
+                    .iter()
+                    .map(|var| solution.get(var).cloned().map(normalize_solution_term))
+                    .collect()
+            })
+            .collect();
+
+        if !ordered {
+            rows.sort_by_cached_key(|row| format_row(&variables, row));
+        }
+
+        Self { variables, rows, ordered }
+    }
+}
+
+fn format_row(variables: &[Variable], row: &[Option<Term>]) -> String {
+    variables
+        .iter()
+        .zip(row)
+        .filter_map(|(var, value)| value.as_ref().map(|value| format!("{var}={value}")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl FromIterator<Triple> for DbState {
+    fn from_iter<T: IntoIterator<Item = Triple>>(iter: T) -> Self {
+        let mut graph: Graph = iter
+            .into_iter()
+            .map(|Triple { subject, predicate, object }| Triple::new(subject, predicate, normalize_term(object)))
+            .collect();
+
+        graph.canonicalize(CanonicalizationAlgorithm::Unstable);
+
+        DbState::Graph(graph)
+    }
+}
+
+impl FromIterator<Quad> for DbState {
+    fn from_iter<T: IntoIterator<Item = Quad>>(iter: T) -> Self {
+        let mut dataset: Dataset = iter
+            .into_iter()
+            .map(|Quad { subject, predicate, object, graph_name }| {
+                Quad::new(subject, predicate, normalize_term(object), graph_name)
+            })
+            .collect();
+
+        dataset.canonicalize(CanonicalizationAlgorithm::Unstable);
+
+        DbState::Dataset(dataset)
+    }
+}
+
+impl serde::Serialize for DbState {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::Error;
+
+        match self {
+            DbState::Graph(_) | DbState::Dataset(_) => serializer.collect_str(self),
+            DbState::Boolean(value) => serializer.serialize_bool(*value),
+            DbState::Absent => serializer.serialize_none(),
+            DbState::Solutions(Solutions { variables, rows, .. }) => {
+                let mut writer = QueryResultsSerializer::from_format(QueryResultsFormat::Json)
+                    .serialize_solutions_to_writer(Vec::new(), variables.clone())
+                    .map_err(S::Error::custom)?;
+
+                for row in rows {
+                    writer
+                        .serialize(
+                            variables
+                                .iter()
+                                .zip(row)
+                                .filter_map(|(var, value)| value.as_ref().map(|value| (var, value))),
+                        )
+                        .map_err(S::Error::custom)?;
+                }
+
+                let json = writer.finish().map_err(S::Error::custom)?;
+                serde_json::from_slice::<serde_json::Value>(&json)
+                    .map_err(S::Error::custom)?
+                    .serialize(serializer)
+            },
+        }
+    }
+}
+
+impl Display for DbState {
+    /// Writes the state line by line, so that two states can be diffed: graphs (datasets) as N-Triples (N-Quads)
+    /// with sorted lines, solutions as one line of `?var=value` pairs per solution
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbState::Graph(graph) => {
+                let mut lines: Vec<String> = graph.iter().map(|triple| format!("{triple} .")).collect();
+                lines.sort();
+
+                for line in lines {
+                    writeln!(f, "{line}")?;
+                }
+
+                Ok(())
+            },
+            DbState::Dataset(dataset) => {
+                let mut lines: Vec<String> = dataset.iter().map(|quad| format!("{quad} .")).collect();
+                lines.sort();
+
+                for line in lines {
+                    writeln!(f, "{line}")?;
+                }
+
+                Ok(())
+            },
+            DbState::Solutions(Solutions { variables, rows, .. }) => {
+                for row in rows {
+                    writeln!(f, "{}", format_row(variables, row))?;
+                }
+
+                Ok(())
+            },
+            DbState::Boolean(value) => writeln!(f, "{value}"),
+            DbState::Absent => writeln!(f, "(graph does not exist)"),
+        }
+    }
+}
+
+/// Blank node labels in query results are arbitrary, so all blank nodes are replaced by the same one
+fn normalize_solution_term(term: Term) -> Term {
+    match term {
+        Term::BlankNode(_) => Term::BlankNode(BlankNode::new_unchecked("b")),
+        term => normalize_term(term),
+    }
+}
+
+fn normalize_term(term: Term) -> Term {
+    match term {
+        Term::Literal(lit) => Term::Literal(normalize_literal(lit)),
+        term => term,
+    }
+}
+
+/// Rewrites literals of well-known datatypes into their canonical lexical form,
+/// e.g. `"01"^^xsd:integer` into `"1"^^xsd:integer`. Literals with invalid lexical forms are left untouched.
+fn normalize_literal(lit: Literal) -> Literal {
+    fn canonical<T: FromStr + Display>(value: &str) -> Option<String> {
+        value.parse::<T>().ok().map(|v| v.to_string())
+    }
+
+    let datatype = lit.datatype();
+
+    let canonical_value = if datatype == xsd::INTEGER
+        || datatype == xsd::LONG
+        || datatype == xsd::INT
+        || datatype == xsd::SHORT
+        || datatype == xsd::BYTE
+        || datatype == xsd::NON_NEGATIVE_INTEGER
+        || datatype == xsd::NON_POSITIVE_INTEGER
+        || datatype == xsd::POSITIVE_INTEGER
+        || datatype == xsd::NEGATIVE_INTEGER
+        || datatype == xsd::UNSIGNED_LONG
+        || datatype == xsd::UNSIGNED_INT
+        || datatype == xsd::UNSIGNED_SHORT
+        || datatype == xsd::UNSIGNED_BYTE
+    {
+        canonical::<Integer>(lit.value())
+    } else if datatype == xsd::DECIMAL {
+        canonical::<Decimal>(lit.value())
+    } else if datatype == xsd::DOUBLE {
+        canonical::<Double>(lit.value())
+    } else if datatype == xsd::FLOAT {
+        canonical::<Float>(lit.value())
+    } else if datatype == xsd::BOOLEAN {
+        canonical::<Boolean>(lit.value())
+    } else {
+        None
+    };
+
+    match canonical_value {
+        Some(value) => Literal::new_typed_literal(value, datatype.into_owned()),
+        None => lit,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(ntriples: &str) -> DbState {
+        DbState::parse(ntriples).unwrap()
+    }
+
+    fn dataset(nquads: &str) -> DbState {
+        DbState::parse_dataset(nquads).unwrap()
+    }
+
+    #[test]
+    fn blank_nodes_are_compared_up_to_renaming() {
+        let state = graph(
+            "<http://example.org/s> <http://example.org/p> _:a .\n\
+             _:a <http://example.org/q> _:b .\n\
+             _:b <http://example.org/q> \"x\" .\n",
+        );
+        let renamed = graph(
+            "_:y <http://example.org/q> \"x\" .\n\
+             <http://example.org/s> <http://example.org/p> _:x .\n\
+             _:x <http://example.org/q> _:y .\n",
+        );
+        // same number of blank nodes and triples, but not isomorphic
+        let swapped = graph(
+            "<http://example.org/s> <http://example.org/p> _:a .\n\
+             _:b <http://example.org/q> _:a .\n\
+             _:b <http://example.org/q> \"x\" .\n",
+        );
+
+        assert_eq!(state, renamed);
+        assert_ne!(state, swapped);
+    }
+
+    #[test]
+    fn literals_are_compared_by_value() {
+        let integer = |value: &str| {
+            graph(&format!(
+                "<http://example.org/s> <http://example.org/p> \"{value}\"^^<http://www.w3.org/2001/XMLSchema#integer> .\n"
+            ))
+        };
+
+        assert_eq!(integer("1"), integer("01"));
+        assert_eq!(integer("1"), integer("+1"));
+        assert_ne!(integer("1"), integer("2"));
+        // invalid lexical forms are compared as they are
+        assert_ne!(integer("a"), integer("b"));
+
+        assert_eq!(
+            graph("<http://example.org/s> <http://example.org/p> \"1.50\"^^<http://www.w3.org/2001/XMLSchema#decimal> .\n"),
+            graph("<http://example.org/s> <http://example.org/p> \"1.5\"^^<http://www.w3.org/2001/XMLSchema#decimal> .\n"),
+        );
+        assert_eq!(
+            graph("<http://example.org/s> <http://example.org/p> \"1\"^^<http://www.w3.org/2001/XMLSchema#boolean> .\n"),
+            graph("<http://example.org/s> <http://example.org/p> \"true\"^^<http://www.w3.org/2001/XMLSchema#boolean> .\n"),
+        );
+        // plain strings are not numbers
+        assert_ne!(
+            graph("<http://example.org/s> <http://example.org/p> \"1\" .\n"),
+            graph("<http://example.org/s> <http://example.org/p> \"01\" .\n"),
+        );
+    }
+
+    #[test]
+    fn datasets_are_compared_per_graph() {
+        let state = dataset(
+            "<http://example.org/s> <http://example.org/p> _:a <http://example.org/g1> .\n\
+             _:a <http://example.org/q> \"01\"^^<http://www.w3.org/2001/XMLSchema#integer> <http://example.org/g1> .\n\
+             <http://example.org/s> <http://example.org/p> \"x\" .\n",
+        );
+        let renamed = dataset(
+            "<http://example.org/s> <http://example.org/p> \"x\" .\n\
+             _:z <http://example.org/q> \"1\"^^<http://www.w3.org/2001/XMLSchema#integer> <http://example.org/g1> .\n\
+             <http://example.org/s> <http://example.org/p> _:z <http://example.org/g1> .\n",
+        );
+        let other_graph = dataset(
+            "<http://example.org/s> <http://example.org/p> _:a <http://example.org/g2> .\n\
+             _:a <http://example.org/q> \"1\"^^<http://www.w3.org/2001/XMLSchema#integer> <http://example.org/g2> .\n\
+             <http://example.org/s> <http://example.org/p> \"x\" .\n",
+        );
+
+        assert_eq!(state, renamed);
+        assert_ne!(state, other_graph);
+        // the same triples in the default graph are a graph, not a dataset
+        assert_ne!(
+            dataset("<http://example.org/s> <http://example.org/p> \"x\" .\n"),
+            graph("<http://example.org/s> <http://example.org/p> \"x\" .\n"),
+        );
+    }
+
+    #[test]
+    fn trig_and_nquads_responses_are_equal() {
+        let nquads = "<http://example.org/s> <http://example.org/p> _:a <http://example.org/g> .\n";
+        let trig = "<http://example.org/g> { <http://example.org/s> <http://example.org/p> [] }\n";
+
+        assert_eq!(
+            DbState::parse_response(nquads.as_bytes(), Some("application/n-quads"), StateForm::Dataset).unwrap(),
+            DbState::parse_response(trig.as_bytes(), Some("application/trig"), StateForm::Dataset).unwrap(),
+        );
+    }
+}
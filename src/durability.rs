@@ -0,0 +1,446 @@
+use crate::{db_state::DbState, operation::StateSource};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{oneshot, watch, Notify, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// An update operation the server has acknowledged (i.e. answered with a 2xx status)
+#[derive(Debug)]
+pub struct AckedOperation {
+    pub update_id: usize,
+    pub source: StateSource,
+    pub expected: DbState,
+}
+
+/// An update operation that was issued but not acknowledged yet
+#[derive(Debug)]
+pub struct PendingOperation {
+    pub update_id: usize,
+    /// The validated states after the steps of the operation, the last one being the state after the whole operation.
+    /// If the server applied (part of) the operation before a kill, one of them must be observed.
+    pub post_states: Vec<(StateSource, DbState)>,
+}
+
+/// The latest acknowledged operation of an update worker, which must survive a server kill
+pub struct DurabilityCheck {
+    pub update_worker_id: usize,
+    pub acked: Arc<AckedOperation>,
+    /// The operation the update worker had in flight when the server was killed. If the server applied it,
+    /// it may have changed the state of the acknowledged operation, so one of its post-states is accepted instead.
+    pub in_flight: Option<Arc<PendingOperation>>,
+}
+
+/// An acknowledged operation that triggers a kill
+/// (see [`DurabilityTracker::arm_kill_trigger`] and [`DurabilityTracker::set_kill_targets`])
+pub struct KillTarget {
+    pub update_worker_id: usize,
+    pub op: Arc<AckedOperation>,
+    /// When the update worker received the acknowledgement
+    pub acked_at: Instant,
+    /// The update worker that applied the operation waits until this is dropped, i.e. until the server was killed
+    pub resume: oneshot::Sender<()>,
+}
+
+/// The operations whose acknowledgements trigger kills, independently of whether a kill is currently awaited
+#[derive(Default)]
+struct KillTargets {
+    /// The operations (by update worker id and update id) that were not acknowledged yet
+    remaining: BTreeSet<(usize, usize)>,
+    /// Acknowledged operations whose kill is still to be issued, in the order of their acknowledgements
+    acked: VecDeque<KillTarget>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct KillCount {
+    started: usize,
+    /// Kill cycles in which the server was restarted and the acknowledged operations were checked
+    completed: usize,
+}
+
+#[derive(Default)]
+struct WorkerProgress {
+    last_acked: Option<Arc<AckedOperation>>,
+    n_acked: usize,
+    in_flight: Option<Arc<PendingOperation>>,
+}
+
+/// Keeps track of the acknowledged operations of all update workers,
+/// so that the kill worker can check that they survived a server kill.
+///
+/// It also allows the kill worker to hold back the requests of the update workers
+/// while it restarts the server and checks the acknowledged operations.
+#[derive(Clone, Default)]
+pub struct DurabilityTracker {
+    progress: Arc<Mutex<BTreeMap<usize, WorkerProgress>>>,
+    recovery: Arc<RwLock<()>>,
+    /// Notified whenever an update worker issues an operation or the server acknowledges one
+    changed: Arc<Notify>,
+    kill_trigger: Arc<Mutex<Option<oneshot::Sender<KillTarget>>>>,
+    kill_targets: Arc<Mutex<KillTargets>>,
+    kills: Arc<watch::Sender<KillCount>>,
+}
+
+impl DurabilityTracker {
+    pub fn register(&self, update_worker_id: usize) -> WorkerDurabilityHandle {
+        self.progress
+            .lock()
+            .unwrap()
+            .insert(update_worker_id, WorkerProgress::default());
+
+        WorkerDurabilityHandle { update_worker_id, tracker: self.clone() }
+    }
+
+    /// Blocks all subsequent requests of the update workers until the returned guard is dropped
+    pub async fn block_requests(&self) -> RwLockWriteGuard<'_, ()> {
+        self.recovery.write().await
+    }
+
+    /// Must be called before the server is killed
+    pub fn kill_started(&self) {
+        self.kills.send_modify(|kills| kills.started += 1);
+    }
+
+    /// Must be called after the server was restarted and the acknowledged operations were checked,
+    /// before the requests of the update workers are unblocked
+    pub fn kill_completed(&self) {
+        self.kills.send_modify(|kills| kills.completed += 1);
+    }
+
+    /// A gate for workers that only read, see [`RestartGate`]
+    pub fn restart_gate(&self, backoff: Duration) -> RestartGate {
+        RestartGate { tracker: self.clone(), backoff }
+    }
+
+    /// The latest acknowledged operation of every update worker, along with the update it has in flight, if any
+    pub fn checkable_operations(&self) -> Vec<DurabilityCheck> {
+        self.progress
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(worker_id, progress)| {
+                Some(DurabilityCheck {
+                    update_worker_id: *worker_id,
+                    acked: progress.last_acked.clone()?,
+                    in_flight: progress.in_flight.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Total number of acknowledged operations over all update workers
+    pub fn n_acked(&self) -> usize {
+        self.progress
+            .lock()
+            .unwrap()
+            .values()
+            .map(|progress| progress.n_acked)
+            .sum()
+    }
+
+    /// Arms a trigger that fires as soon as the server acknowledges the next operation that is not a kill target,
+    /// replacing any trigger that is still armed
+    pub fn arm_kill_trigger(&self) -> oneshot::Receiver<KillTarget> {
+        let (notify, triggered) = oneshot::channel();
+        *self.kill_trigger.lock().unwrap() = Some(notify);
+        triggered
+    }
+
+    /// Must be called before the update workers are started. The acknowledgement of every given operation
+    /// (by update worker id and update id) is recorded, and the update worker waits until the server was killed,
+    /// see [`Self::next_acked_kill_target`].
+    pub fn set_kill_targets(&self, targets: impl IntoIterator<Item = (usize, usize)>) {
+        self.kill_targets.lock().unwrap().remaining = targets.into_iter().collect();
+    }
+
+    /// Waits until a kill target is acknowledged, or returns the earliest one that was acknowledged
+    /// since the last call. Returns `None` once no kill target can be acknowledged anymore.
+    pub async fn next_acked_kill_target(&self) -> Option<KillTarget> {
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            {
+                let mut targets = self.kill_targets.lock().unwrap();
+
+                if let Some(target) = targets.acked.pop_front() {
+                    return Some(target);
+                }
+
+                if targets.remaining.is_empty() {
+                    return None;
+                }
+            }
+
+            changed.await;
+        }
+    }
+
+    /// The kill targets (by update worker id and update id) that were not acknowledged yet
+    pub fn remaining_kill_targets(&self) -> Vec<(usize, usize)> {
+        self.kill_targets.lock().unwrap().remaining.iter().copied().collect()
+    }
+
+    /// Waits until the server acknowledged at least `n_acked` operations in total
+    pub async fn wait_for_acks(&self, n_acked: usize) {
+        self.wait_until(|progress| progress.values().map(|progress| progress.n_acked).sum::<usize>() >= n_acked)
+            .await
+    }
+
+    /// Waits until any update worker has an update in flight
+    pub async fn wait_for_in_flight(&self) {
+        self.wait_until(|progress| progress.values().any(|progress| progress.in_flight.is_some()))
+            .await
+    }
+
+    async fn wait_until(&self, f: impl Fn(&BTreeMap<usize, WorkerProgress>) -> bool) {
+        loop {
+            // registering for the notification before checking the progress ensures that no change is missed
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            if f(&self.progress.lock().unwrap()) {
+                return;
+            }
+
+            changed.await;
+        }
+    }
+}
+
+/// Lets workers that only read wait out a restart of the server,
+/// instead of sending requests to a server that is known to be unavailable
+#[derive(Clone)]
+pub struct RestartGate {
+    tracker: DurabilityTracker,
+    backoff: Duration,
+}
+
+impl RestartGate {
+    /// Must be called after the server could not be reached.
+    /// Waits until the kill worker restarted the server and checked the acknowledged operations.
+    pub async fn wait(&self) {
+        // the kill worker only closes the gate after the kill, so the request may have failed just before that
+        tokio::time::sleep(self.backoff).await;
+        drop(self.tracker.recovery.read().await);
+    }
+}
+
+/// The view of a single update worker on the [`DurabilityTracker`]
+pub struct WorkerDurabilityHandle {
+    update_worker_id: usize,
+    tracker: DurabilityTracker,
+}
+
+impl WorkerDurabilityHandle {
+    /// Must be held while issuing a request to the server
+    pub async fn request_permit(&self) -> RwLockReadGuard<'_, ()> {
+        self.tracker.recovery.read().await
+    }
+
+    /// The number of completed kill cycles, to be passed to [`Self::wait_for_kill_cycle`]
+    pub fn completed_kills(&self) -> usize {
+        self.tracker.kills.borrow().completed
+    }
+
+    /// Must be called after a request failed with a connection error. If a kill started after `completed_kills`
+    /// kill cycles were completed, this waits until that kill cycle is completed, i.e. until the server
+    /// was restarted. Otherwise the error was not caused by a kill and `false` is returned.
+    pub async fn wait_for_kill_cycle(&self, completed_kills: usize) -> bool {
+        let mut kills = self.tracker.kills.subscribe();
+        let started = kills.borrow().started;
+
+        if started == completed_kills {
+            return false;
+        }
+
+        let _ = kills.wait_for(|kills| kills.completed >= started).await;
+        true
+    }
+
+    /// Must be called before the first request of an operation is sent
+    pub fn issued(&self, update_id: usize, post_states: Vec<(StateSource, DbState)>) {
+        // earlier operations of this worker that were never acknowledged cannot trigger their kill anymore
+        let missed: Vec<_> = {
+            let mut targets = self.tracker.kill_targets.lock().unwrap();
+            let missed: Vec<_> = targets
+                .remaining
+                .range((self.update_worker_id, 0)..(self.update_worker_id, update_id))
+                .copied()
+                .collect();
+
+            for target in &missed {
+                targets.remaining.remove(target);
+            }
+
+            missed
+        };
+
+        for (_, missed_id) in missed {
+            tracing::warn!(
+                "Update {missed_id} of update worker {} was not acknowledged, so it will not trigger a kill",
+                self.update_worker_id
+            );
+        }
+
+        let pending = Arc::new(PendingOperation { update_id, post_states });
+        self.with_progress(|progress| progress.in_flight = Some(pending));
+    }
+
+    /// Must be called as soon as the server acknowledged the last request of an operation.
+    /// If the operation triggers a kill, this waits until the server was killed.
+    pub async fn acknowledged(&self, update_id: usize, source: &StateSource, expected: &DbState) {
+        let acked_at = Instant::now();
+        let acked = Arc::new(AckedOperation { update_id, source: source.clone(), expected: expected.clone() });
+
+        self.with_progress(|progress| {
+            progress.last_acked = Some(acked.clone());
+            progress.n_acked += 1;
+            progress.in_flight = None;
+        });
+
+        let (resume, resumed) = oneshot::channel();
+        let target = KillTarget { update_worker_id: self.update_worker_id, op: acked, acked_at, resume };
+
+        let triggered = {
+            let mut kill_targets = self.tracker.kill_targets.lock().unwrap();
+
+            if kill_targets.remaining.remove(&(self.update_worker_id, update_id)) {
+                kill_targets.acked.push_back(target);
+                self.tracker.changed.notify_waiters();
+                true
+            } else {
+                let trigger = self.tracker.kill_trigger.lock().unwrap().take();
+                trigger.is_some_and(|trigger| trigger.send(target).is_ok())
+            }
+        };
+
+        // the worker must not continue before the kill, as its next operation could overwrite the state to check
+        if triggered {
+            let _ = resumed.await;
+        }
+    }
+
+    fn with_progress(&self, f: impl FnOnce(&mut WorkerProgress)) {
+        let mut progress = self.tracker.progress.lock().unwrap();
+        f(progress.get_mut(&self.update_worker_id).unwrap());
+        self.tracker.changed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn source() -> StateSource {
+        StateSource::GspGraph("http://example.org/g".to_owned())
+    }
+
+    /// Acknowledges an operation in the background, as it may wait until the server was killed
+    fn acknowledge(handle: WorkerDurabilityHandle, update_id: usize) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move { handle.acknowledged(update_id, &source(), &DbState::Absent).await })
+    }
+
+    #[tokio::test]
+    async fn target_acknowledged_before_waiting_is_returned() {
+        let tracker = DurabilityTracker::default();
+        tracker.set_kill_targets([(1, 0)]);
+
+        // no kill is awaited while the target is acknowledged
+        let acked = acknowledge(tracker.register(1), 0);
+        tokio::time::timeout(TIMEOUT, tracker.wait_for_acks(1)).await.unwrap();
+
+        let target = tokio::time::timeout(TIMEOUT, tracker.next_acked_kill_target())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((target.update_worker_id, target.op.update_id), (1, 0));
+
+        // the update worker only continues once the server was killed
+        assert!(!acked.is_finished());
+        drop(target);
+        tokio::time::timeout(TIMEOUT, acked).await.unwrap().unwrap();
+
+        assert!(tracker.next_acked_kill_target().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn armed_trigger_fires_only_once() {
+        let tracker = DurabilityTracker::default();
+        let handle = Arc::new(tracker.register(1));
+        let triggered = tracker.arm_kill_trigger();
+
+        let first = {
+            let handle = handle.clone();
+            tokio::spawn(async move { handle.acknowledged(0, &source(), &DbState::Absent).await })
+        };
+
+        let target = tokio::time::timeout(TIMEOUT, triggered).await.unwrap().unwrap();
+        assert_eq!(target.op.update_id, 0);
+        drop(target);
+        tokio::time::timeout(TIMEOUT, first).await.unwrap().unwrap();
+
+        // the trigger is used up, so the next acknowledgement does not wait for a kill
+        tokio::time::timeout(TIMEOUT, handle.acknowledged(1, &source(), &DbState::Absent))
+            .await
+            .unwrap();
+        assert_eq!(tracker.n_acked(), 2);
+    }
+
+    #[tokio::test]
+    async fn skipped_targets_are_dropped() {
+        let tracker = DurabilityTracker::default();
+        tracker.set_kill_targets([(1, 0), (1, 2), (2, 0)]);
+        let handle = tracker.register(1);
+
+        handle.issued(1, Vec::new());
+        assert_eq!(tracker.remaining_kill_targets(), [(1, 2), (2, 0)]);
+
+        // targets of other workers are kept
+        handle.issued(3, Vec::new());
+        assert_eq!(tracker.remaining_kill_targets(), [(2, 0)]);
+    }
+
+    #[tokio::test]
+    async fn connection_error_without_kill_is_not_caused_by_a_kill() {
+        let tracker = DurabilityTracker::default();
+        let handle = tracker.register(1);
+
+        assert!(!handle.wait_for_kill_cycle(handle.completed_kills()).await);
+    }
+
+    #[tokio::test]
+    async fn connection_error_during_kill_waits_until_kill_completed() {
+        let tracker = DurabilityTracker::default();
+        let handle = tracker.register(1);
+        let completed_kills = handle.completed_kills();
+
+        tracker.kill_started();
+        let waiting = tokio::spawn(async move { handle.wait_for_kill_cycle(completed_kills).await });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        tracker.kill_completed();
+        assert!(tokio::time::timeout(TIMEOUT, waiting).await.unwrap().unwrap());
+    }
+
+    #[tokio::test]
+    async fn connection_error_after_completed_kill_returns_immediately() {
+        let tracker = DurabilityTracker::default();
+        let handle = tracker.register(1);
+        let completed_kills = handle.completed_kills();
+
+        tracker.kill_started();
+        tracker.kill_completed();
+
+        assert!(tokio::time::timeout(TIMEOUT, handle.wait_for_kill_cycle(completed_kills))
+            .await
+            .unwrap());
+    }
+}
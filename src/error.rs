@@ -1,42 +1,237 @@
+use serde::{Serialize, Serializer};
 use std::{
     fmt::{Display, Formatter},
     io,
 };
+use strum::IntoStaticStr;
 use thiserror::Error;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct InvalidStateVerboseInfo {
     pub query: String,
     pub expected: String,
     pub actual: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+pub struct TransitionVerboseInfo {
+    pub query: String,
+    pub pre_state: String,
+    pub post_state: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RollbackVerboseInfo {
+    /// The request that was rejected
+    pub request: String,
+    pub query: String,
+    pub pre_state: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Serialize)]
 pub struct UpdateFailedVerboseInfo {
     pub query: String,
 }
 
+#[derive(Debug, Serialize)]
+pub struct InvariantVerboseInfo {
+    pub query: String,
+    pub actual: String,
+}
+
 #[derive(Debug, Error)]
+pub enum ReadStateError {
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("Unable to parse result as RDF: {0}")]
+    Syntax(#[from] oxttl::TurtleSyntaxError),
+    #[error("Unable to parse result as SPARQL JSON results: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unable to parse result as SPARQL query results: {0}")]
+    Results(#[from] sparesults::QueryResultsSyntaxError),
+    #[error("Expected {0} as the result of the query")]
+    UnexpectedResultForm(&'static str),
+    #[error("Unsupported result content type {0:?}")]
+    UnsupportedFormat(String),
+}
+
+#[derive(Debug, Error)]
+pub enum UpdateError {
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("Unexpected response status {0}")]
+    UnexpectedStatus(reqwest::StatusCode),
+}
+
+/// Serialized with the name of the variant in snake case as `kind`, see [`WorkerError::kind`]
+#[derive(Debug, Error, Serialize, IntoStaticStr)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
 pub enum WorkerError {
     InvalidState {
         update_id: usize,
         verbose_info: Option<InvalidStateVerboseInfo>,
     },
+    DurabilityViolation {
+        update_worker_id: usize,
+        update_id: usize,
+        verbose_info: Option<InvalidStateVerboseInfo>,
+    },
+    PartiallyApplied {
+        update_id: usize,
+        verbose_info: Option<TransitionVerboseInfo>,
+    },
+    IsolationViolation {
+        update_worker_id: usize,
+        update_id: usize,
+        verbose_info: Option<TransitionVerboseInfo>,
+    },
     ReadFailed {
         query: String,
-        err: reqwest::Error,
+        #[serde(serialize_with = "serialize_display")]
+        err: ReadStateError,
+    },
+    UnexpectedResultCount {
+        query_name: String,
+        expected: usize,
+        actual: usize,
     },
     UpdateVerifyFailed {
         update_id: usize,
-        err: reqwest::Error,
+        #[serde(serialize_with = "serialize_display")]
+        err: ReadStateError,
     },
     UpdateFailed {
         update_id: usize,
-        err: reqwest::Error,
+        #[serde(serialize_with = "serialize_display")]
+        err: UpdateError,
+        verbose_info: Option<UpdateFailedVerboseInfo>,
+    },
+    UnexpectedSuccess {
+        update_id: usize,
         verbose_info: Option<UpdateFailedVerboseInfo>,
     },
-    KillFailed(io::Error),
-    RestartFailed(io::Error),
+    /// A request was rejected as expected, but still changed the state
+    RollbackViolation {
+        update_id: usize,
+        verbose_info: Option<RollbackVerboseInfo>,
+    },
+    InvariantViolation {
+        invariant: String,
+        /// The value implied by the acknowledged operations
+        min: i64,
+        /// The value implied by all issued operations, including those still in flight
+        max: i64,
+        /// `None` if the query did not return a single integer
+        actual: Option<i64>,
+        verbose_info: Option<InvariantVerboseInfo>,
+    },
+    ConservationViolation {
+        invariant: String,
+        expected: i64,
+        /// `None` if the query did not return a single integer
+        actual: Option<i64>,
+        /// When the reader received the value, in seconds since the Unix epoch
+        observed_at_unix_s: f64,
+        verbose_info: Option<InvariantVerboseInfo>,
+    },
+    KillFailed {
+        #[serde(serialize_with = "serialize_display")]
+        err: io::Error,
+    },
+    RestartFailed {
+        #[serde(serialize_with = "serialize_display")]
+        err: io::Error,
+    },
+    PowerCutFailed {
+        #[serde(serialize_with = "serialize_display")]
+        err: io::Error,
+    },
+    ServerNotReady {
+        deadline_s: f64,
+        /// Why the last readiness probe failed
+        err: String,
+    },
+    /// An update worker was stopped before completing all of its operations, because the test could not continue
+    Stopped {
+        update_id: usize,
+    },
+}
+
+impl WorkerError {
+    /// The name of the error variant in snake case, as used for `kind` in the serialized error
+    pub fn kind(&self) -> &'static str {
+        self.into()
+    }
+
+    /// Whether this error means that the triplestore behaved incorrectly,
+    /// as opposed to the test being unable to execute (e.g. because a request failed)
+    pub fn is_violation(&self) -> bool {
+        matches!(
+            self,
+            WorkerError::InvalidState { .. }
+                | WorkerError::DurabilityViolation { .. }
+                | WorkerError::PartiallyApplied { .. }
+                | WorkerError::IsolationViolation { .. }
+                | WorkerError::UnexpectedResultCount { .. }
+                | WorkerError::UnexpectedSuccess { .. }
+                | WorkerError::RollbackViolation { .. }
+                | WorkerError::InvariantViolation { .. }
+                | WorkerError::ConservationViolation { .. }
+        )
+    }
+
+    /// The id of the update operation this error is about, if any
+    pub fn update_id(&self) -> Option<usize> {
+        match self {
+            WorkerError::InvalidState { update_id, .. }
+            | WorkerError::DurabilityViolation { update_id, .. }
+            | WorkerError::PartiallyApplied { update_id, .. }
+            | WorkerError::IsolationViolation { update_id, .. }
+            | WorkerError::UpdateVerifyFailed { update_id, .. }
+            | WorkerError::UpdateFailed { update_id, .. }
+            | WorkerError::UnexpectedSuccess { update_id, .. }
+            | WorkerError::RollbackViolation { update_id, .. }
+            | WorkerError::Stopped { update_id } => Some(*update_id),
+            WorkerError::ReadFailed { .. }
+            | WorkerError::UnexpectedResultCount { .. }
+            | WorkerError::InvariantViolation { .. }
+            | WorkerError::ConservationViolation { .. }
+            | WorkerError::KillFailed { .. }
+            | WorkerError::RestartFailed { .. }
+            | WorkerError::PowerCutFailed { .. }
+            | WorkerError::ServerNotReady { .. } => None,
+        }
+    }
+}
+
+impl WorkerError {
+    /// Removes the verbose info (e.g. the expected and actual states) from this error
+    pub fn clear_verbose_info(&mut self) {
+        match self {
+            WorkerError::InvalidState { verbose_info, .. } | WorkerError::DurabilityViolation { verbose_info, .. } => {
+                *verbose_info = None
+            },
+            WorkerError::PartiallyApplied { verbose_info, .. }
+            | WorkerError::IsolationViolation { verbose_info, .. } => *verbose_info = None,
+            WorkerError::UpdateFailed { verbose_info, .. } | WorkerError::UnexpectedSuccess { verbose_info, .. } => {
+                *verbose_info = None
+            },
+            WorkerError::RollbackViolation { verbose_info, .. } => *verbose_info = None,
+            WorkerError::InvariantViolation { verbose_info, .. }
+            | WorkerError::ConservationViolation { verbose_info, .. } => *verbose_info = None,
+            WorkerError::ReadFailed { .. }
+            | WorkerError::UnexpectedResultCount { .. }
+            | WorkerError::UpdateVerifyFailed { .. }
+            | WorkerError::KillFailed { .. }
+            | WorkerError::RestartFailed { .. }
+            | WorkerError::PowerCutFailed { .. }
+            | WorkerError::ServerNotReady { .. }
+            | WorkerError::Stopped { .. } => (),
+        }
+    }
 }
 
 impl Display for WorkerError {
@@ -60,6 +255,62 @@ impl Display for WorkerError {
                     Ok(())
                 }
             },
+            WorkerError::DurabilityViolation { update_worker_id, update_id, verbose_info } => {
+                write!(
+                    f,
+                    "Acknowledged update {update_id} of update worker {update_worker_id} did not survive a server kill"
+                )?;
+
+                if let Some(InvalidStateVerboseInfo { query, expected, actual }) = verbose_info {
+                    writeln!(
+                        f,
+                        "\nQuery:\n{}\n\nDifference between expected and actual state:\n{}",
+                        query,
+                        prettydiff::diff_lines(expected, actual)
+                    )?;
+                }
+
+                Ok(())
+            },
+            WorkerError::PartiallyApplied { update_id, verbose_info } => {
+                write!(
+                    f,
+                    "Update {update_id} was interrupted by a server kill and left a state \
+                     that is neither the state before nor after the update"
+                )?;
+
+                if let Some(TransitionVerboseInfo { query, pre_state, post_state, actual }) = verbose_info {
+                    writeln!(
+                        f,
+                        "\nQuery:\n{}\n\nDifference between state before update and actual state:\n{}\n\n\
+                         Difference between state after update and actual state:\n{}",
+                        query,
+                        prettydiff::diff_lines(pre_state, actual),
+                        prettydiff::diff_lines(post_state, actual)
+                    )?;
+                }
+
+                Ok(())
+            },
+            WorkerError::IsolationViolation { update_worker_id, update_id, verbose_info } => {
+                write!(
+                    f,
+                    "A reader observed a partially applied update {update_id} of update worker {update_worker_id}"
+                )?;
+
+                if let Some(TransitionVerboseInfo { query, pre_state, post_state, actual }) = verbose_info {
+                    writeln!(
+                        f,
+                        "\nQuery:\n{}\n\nDifference between state before update and observed state:\n{}\n\n\
+                         Difference between state after update and observed state:\n{}",
+                        query,
+                        prettydiff::diff_lines(pre_state, actual),
+                        prettydiff::diff_lines(post_state, actual)
+                    )?;
+                }
+
+                Ok(())
+            },
             WorkerError::UpdateVerifyFailed { update_id, err } => {
                 write!(
                     f,
@@ -75,14 +326,218 @@ impl Display for WorkerError {
                     Ok(())
                 }
             },
+            WorkerError::UnexpectedSuccess { update_id, verbose_info } => {
+                write!(
+                    f,
+                    "Update {update_id} was expected to be rejected, but the server accepted it"
+                )?;
+
+                if let Some(UpdateFailedVerboseInfo { query }) = verbose_info {
+                    write!(f, "\nQuery: {query}")
+                } else {
+                    Ok(())
+                }
+            },
+            WorkerError::RollbackViolation { update_id, verbose_info } => {
+                write!(
+                    f,
+                    "Update {update_id} was rejected by the server as expected, but changed the state"
+                )?;
+
+                if let Some(RollbackVerboseInfo { request, query, pre_state, actual }) = verbose_info {
+                    writeln!(
+                        f,
+                        "\nRequest: {}\n\nQuery:\n{}\n\nDifference between state before update and actual state:\n{}",
+                        request,
+                        query,
+                        prettydiff::diff_lines(pre_state, actual)
+                    )?;
+                }
+
+                Ok(())
+            },
             WorkerError::ReadFailed { query, err } => {
                 writeln!(
                     f,
                     "A reader was unable to execute a query. Error: {err}\nQuery: {query}"
                 )
             },
-            WorkerError::KillFailed(err) => write!(f, "Unable to kill server. Error: {err}"),
-            WorkerError::RestartFailed(err) => write!(f, "Unable to restart server. Error: {err}"),
+            WorkerError::UnexpectedResultCount { query_name, expected, actual } => {
+                write!(
+                    f,
+                    "A reader received {actual} results for query {query_name}, but expected {expected}"
+                )
+            },
+            WorkerError::InvariantViolation { invariant, min, max, actual, verbose_info } => {
+                match actual {
+                    Some(actual) if min == max => write!(
+                        f,
+                        "Invariant {invariant} does not hold, expected {min}, but observed {actual}"
+                    )?,
+                    Some(actual) => write!(
+                        f,
+                        "Invariant {invariant} does not hold, expected a value between {min} and {max}, \
+                         but observed {actual}"
+                    )?,
+                    None => write!(
+                        f,
+                        "Invariant {invariant} does not hold, its query did not return a single integer"
+                    )?,
+                }
+
+                match actual {
+                    Some(actual) if actual < min => write!(
+                        f,
+                        " (acknowledged increments were lost or decrements were applied more than once)"
+                    )?,
+                    Some(actual) if actual > max => write!(
+                        f,
+                        " (increments were applied more than once or acknowledged decrements were lost)"
+                    )?,
+                    _ => (),
+                }
+
+                if let Some(InvariantVerboseInfo { query, actual }) = verbose_info {
+                    writeln!(f, "\nQuery:\n{query}\n\nObserved state:\n{actual}")?;
+                }
+
+                Ok(())
+            },
+            WorkerError::ConservationViolation { invariant, expected, actual, observed_at_unix_s, verbose_info } => {
+                match actual {
+                    Some(actual) => write!(
+                        f,
+                        "A reader observed {actual} as the value of invariant {invariant} at {observed_at_unix_s:.3} \
+                         (Unix time), but it must always be {expected}"
+                    )?,
+                    None => write!(
+                        f,
+                        "The query of invariant {invariant} did not return a single integer \
+                         at {observed_at_unix_s:.3} (Unix time)"
+                    )?,
+                }
+
+                if let Some(InvariantVerboseInfo { query, actual }) = verbose_info {
+                    writeln!(f, "\nQuery:\n{query}\n\nObserved state:\n{actual}")?;
+                }
+
+                Ok(())
+            },
+            WorkerError::KillFailed { err } => write!(f, "Unable to kill server. Error: {err}"),
+            WorkerError::RestartFailed { err } => write!(f, "Unable to restart server. Error: {err}"),
+            WorkerError::PowerCutFailed { err } => {
+                write!(f, "Unable to cut the power of the server's storage. Error: {err}")
+            },
+            WorkerError::ServerNotReady { deadline_s, err } => {
+                write!(
+                    f,
+                    "The server did not become ready within {deadline_s}s. Last error: {err}"
+                )
+            },
+            WorkerError::Stopped { update_id } => {
+                write!(f, "Stopped before update {update_id} was completed")
+            },
         }
     }
 }
+
+/// Serializes the underlying errors, which do not implement [`Serialize`], as their messages
+fn serialize_display<S: Serializer>(err: &impl Display, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kind_matches_the_serialized_tag() {
+        let read_error = || ReadStateError::UnexpectedResultForm("a graph");
+        let errors = [
+            WorkerError::InvalidState { update_id: 1, verbose_info: None },
+            WorkerError::DurabilityViolation { update_worker_id: 1, update_id: 1, verbose_info: None },
+            WorkerError::PartiallyApplied { update_id: 1, verbose_info: None },
+            WorkerError::IsolationViolation { update_worker_id: 1, update_id: 1, verbose_info: None },
+            WorkerError::ReadFailed { query: "q".to_owned(), err: read_error() },
+            WorkerError::UnexpectedResultCount { query_name: "q".to_owned(), expected: 1, actual: 2 },
+            WorkerError::UpdateVerifyFailed { update_id: 1, err: read_error() },
+            WorkerError::UpdateFailed {
+                update_id: 1,
+                err: UpdateError::UnexpectedStatus(reqwest::StatusCode::BAD_REQUEST),
+                verbose_info: None,
+            },
+            WorkerError::UnexpectedSuccess { update_id: 1, verbose_info: None },
+            WorkerError::RollbackViolation { update_id: 1, verbose_info: None },
+            WorkerError::InvariantViolation {
+                invariant: "i".to_owned(),
+                min: 0,
+                max: 1,
+                actual: None,
+                verbose_info: None,
+            },
+            WorkerError::ConservationViolation {
+                invariant: "i".to_owned(),
+                expected: 1,
+                actual: None,
+                observed_at_unix_s: 0.0,
+                verbose_info: None,
+            },
+            WorkerError::KillFailed { err: io::Error::other("no such process") },
+            WorkerError::RestartFailed { err: io::Error::other("no such file") },
+            WorkerError::PowerCutFailed { err: io::Error::other("no device") },
+            WorkerError::ServerNotReady { deadline_s: 1.0, err: "refused".to_owned() },
+            WorkerError::Stopped { update_id: 1 },
+        ];
+
+        // fails to compile when a variant is added, as a reminder to add it to `errors` as well
+        for error in &errors {
+            match error {
+                WorkerError::InvalidState { .. }
+                | WorkerError::DurabilityViolation { .. }
+                | WorkerError::PartiallyApplied { .. }
+                | WorkerError::IsolationViolation { .. }
+                | WorkerError::ReadFailed { .. }
+                | WorkerError::UnexpectedResultCount { .. }
+                | WorkerError::UpdateVerifyFailed { .. }
+                | WorkerError::UpdateFailed { .. }
+                | WorkerError::UnexpectedSuccess { .. }
+                | WorkerError::RollbackViolation { .. }
+                | WorkerError::InvariantViolation { .. }
+                | WorkerError::ConservationViolation { .. }
+                | WorkerError::KillFailed { .. }
+                | WorkerError::RestartFailed { .. }
+                | WorkerError::PowerCutFailed { .. }
+                | WorkerError::ServerNotReady { .. }
+                | WorkerError::Stopped { .. } => (),
+            }
+        }
+
+        for error in errors {
+            let json = serde_json::to_value(&error).unwrap();
+            assert_eq!(json["kind"], error.kind());
+        }
+    }
+
+    #[test]
+    fn underlying_errors_are_serialized_as_their_messages() {
+        let error = WorkerError::UpdateFailed {
+            update_id: 3,
+            err: UpdateError::UnexpectedStatus(reqwest::StatusCode::BAD_REQUEST),
+            verbose_info: None,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "kind": "update_failed",
+                "update_id": 3,
+                "err": "Unexpected response status 400 Bad Request",
+                "verbose_info": null,
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(WorkerError::KillFailed { err: io::Error::other("no such process") }).unwrap(),
+            serde_json::json!({ "kind": "kill_failed", "err": "no such process" })
+        );
+    }
+}
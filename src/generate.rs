@@ -0,0 +1,1138 @@
+use crate::{
+    invariant::{Invariant, INVARIANTS_FILE},
+    operation::{Endpoint, Expect, Method, Request, StateSource, UpdateOperation, Validate},
+};
+use anyhow::Context;
+use clap::{Parser, ValueEnum};
+use oxrdf::{
+    vocab::rdf, Dataset, GraphNameRef, Literal, NamedNode, NamedNodeRef, NamedOrBlankNodeRef, Quad, QuadRef, TermRef,
+    Triple,
+};
+use oxttl::{NQuadsParser, NTriplesParser};
+use rand::{
+    distr::{weighted::WeightedIndex, Distribution},
+    rngs::StdRng,
+    seq::{IndexedRandom, SliceRandom},
+    Rng, SeedableRng,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::File,
+    io::{self, BufReader},
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Subject of the triples that are written into the named graph of a subject.
+/// Using a dedicated subject keeps them apart from the default graph triples in the validation queries.
+const GRAPH_SUBJECT: NamedNodeRef = NamedNodeRef::new_unchecked("http://www.example.org/graph");
+const INSERT_PREDICATE: NamedNodeRef = NamedNodeRef::new_unchecked("http://www.example.org/test");
+const INSERT_OBJECT_PREFIX: &str = "http://www.example.org/test/";
+
+/// The named graph all workers insert into with the `shared-insert-data` and `shared-gsp-post` operations.
+/// Within it, every worker uses its own subject, so that its part of the graph can still be validated.
+const SHARED_GRAPH: NamedNodeRef = NamedNodeRef::new_unchecked("http://www.example.org/shared");
+const SHARED_SUBJECT_PREFIX: &str = "http://www.example.org/worker/";
+
+/// The invariant that the shared graph contains exactly the triples of the applied operations
+const SHARED_GRAPH_SIZE: &str = "shared_graph_size";
+
+/// The counters all workers increment with the `shared-increment` operations
+const COUNTER_PREFIX: &str = "http://www.example.org/counter/";
+const COUNT_PREDICATE: NamedNodeRef = NamedNodeRef::new_unchecked("http://www.example.org/count");
+
+/// The accounts the workers transfer money between with the `transfer` operations. Accounts may be overdrawn.
+const ACCOUNT_PREFIX: &str = "http://www.example.org/account/";
+const BALANCE_PREDICATE: NamedNodeRef = NamedNodeRef::new_unchecked("http://www.example.org/balance");
+/// The total amount a worker transferred, which is used to validate its transfers
+const TRANSFERRED_PREDICATE: NamedNodeRef = NamedNodeRef::new_unchecked("http://www.example.org/transferred");
+
+/// The invariant that transfers do not change the sum of all balances
+const TOTAL_BALANCE: &str = "total_balance";
+
+/// Amount of money moved by a transfer
+const TRANSFER_AMOUNT: Range<i64> = 1..100;
+
+/// Number of triples inserted by INSERT DATA, GSP POST and GSP PUT operations
+const INSERT_SIZE: Range<usize> = 1..10;
+
+/// Number of triples deleted by DELETE DATA operations
+const DELETE_SIZE: Range<usize> = 1..10;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OperationKind {
+    InsertData,
+    DeleteData,
+    GspPost,
+    GspPut,
+    GspDelete,
+    /// INSERT DATA into the graph shared by all workers
+    SharedInsertData,
+    /// GSP POST to the graph shared by all workers
+    SharedGspPost,
+    /// Read-modify-write increment (DELETE/INSERT WHERE) of a counter shared by all workers
+    SharedIncrement,
+    /// Transfer of money between two accounts shared by all workers
+    Transfer,
+}
+
+impl OperationKind {
+    /// Whether the operation works on a subject of its own, as opposed to the data shared by all workers
+    fn draws_subject(self) -> bool {
+        match self {
+            OperationKind::InsertData
+            | OperationKind::DeleteData
+            | OperationKind::GspPost
+            | OperationKind::GspPut
+            | OperationKind::GspDelete => true,
+            OperationKind::SharedInsertData
+            | OperationKind::SharedGspPost
+            | OperationKind::SharedIncrement
+            | OperationKind::Transfer => false,
+        }
+    }
+}
+
+/// An operation kind together with its relative frequency, written as `KIND[=WEIGHT]`
+#[derive(Clone, Debug)]
+pub struct WeightedOperationKind {
+    kind: OperationKind,
+    weight: u32,
+}
+
+impl FromStr for WeightedOperationKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, weight) = match s.split_once('=') {
+            Some((kind, weight)) => (
+                kind,
+                weight.parse().map_err(|e| format!("Invalid weight {weight:?}: {e}"))?,
+            ),
+            None => (s, 1),
+        };
+
+        Ok(Self { kind: OperationKind::from_str(kind, true)?, weight })
+    }
+}
+
+#[derive(Parser)]
+pub struct GenerateOpts {
+    /// Number of update workers to generate operations for
+    #[clap(short = 'w', long, default_value_t = 4)]
+    num_update_workers: usize,
+
+    /// Number of operations per update worker
+    #[clap(short = 'n', long, default_value_t = 20)]
+    num_operations: usize,
+
+    /// The kinds of operations to generate and their relative weights,
+    /// e.g. `insert-data=2,gsp-put=1`. Kinds without explicit weight have weight 1.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "insert-data,delete-data,gsp-post,gsp-put,gsp-delete"
+    )]
+    mix: Vec<WeightedOperationKind>,
+
+    /// Only use subjects with this rdf:type (e.g. http://xmlns.com/foaf/0.1/Person).
+    /// If not provided, all IRI subjects in the default graph are used.
+    #[clap(long)]
+    subject_type: Option<String>,
+
+    /// Number of counters the workers share for `shared-increment` operations
+    #[clap(long, default_value_t = 1)]
+    num_counters: usize,
+
+    /// Number of accounts the workers share for `transfer` operations
+    #[clap(long, default_value_t = 10)]
+    num_accounts: usize,
+
+    /// Seed for the random number generator, to make the generated workload reproducible
+    #[clap(long)]
+    seed: Option<u64>,
+
+    /// The dataset (N-Triples or N-Quads) that the triplestore contains when `verify` starts
+    dataset: PathBuf,
+
+    /// Directory to write the workload to, will contain one `worker_N` directory per update worker
+    output_dir: PathBuf,
+}
+
+/// Generates a workload for the `verify` subcommand.
+///
+/// The expected states are computed by applying the operations to an in-memory copy of the dataset.
+/// Each worker works on its own set of subjects (and the named graphs named after them),
+/// so the expected states are independent of how the workers interleave. The exception are the `shared-*`
+/// operations, which all write to the same graph. Their combined effect is checked by an invariant.
+pub fn generate(opts: &GenerateOpts) -> anyhow::Result<()> {
+    anyhow::ensure!(opts.num_operations > 0, "Number of operations must be at least 1");
+    anyhow::ensure!(
+        opts.mix.iter().any(|k| k.weight > 0),
+        "Operation mix must contain at least one operation with weight > 0"
+    );
+    anyhow::ensure!(opts.num_counters > 0, "Number of counters must be at least 1");
+    anyhow::ensure!(opts.num_accounts > 1, "Number of accounts must be at least 2");
+
+    let subject_type = opts
+        .subject_type
+        .as_deref()
+        .map(NamedNode::new)
+        .transpose()
+        .context("Invalid subject type")?;
+
+    let dataset = load_dataset(&opts.dataset)?;
+    let mut rng = match opts.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+
+    let mut subjects = candidate_subjects(&dataset, subject_type.as_ref().map(NamedNode::as_ref));
+    subjects.shuffle(&mut rng);
+
+    // operations on the shared data do not need any subjects of the dataset
+    let draws_subjects = opts.mix.iter().any(|k| k.weight > 0 && k.kind.draws_subject());
+
+    anyhow::ensure!(
+        !draws_subjects || subjects.len() >= opts.num_update_workers * opts.num_operations,
+        "Dataset only contains {} suitable subjects, but {} workers with {} operations each require {}",
+        subjects.len(),
+        opts.num_update_workers,
+        opts.num_operations,
+        opts.num_update_workers * opts.num_operations
+    );
+
+    let invariants = invariants(&dataset, opts)?;
+    let mut generator = WorkloadGenerator {
+        dataset,
+        rng,
+        next_object: 0,
+        num_counters: opts.num_counters,
+        num_accounts: opts.num_accounts,
+    };
+
+    std::fs::create_dir_all(&opts.output_dir).context("Unable to create output directory")?;
+
+    let invariants_path = opts.output_dir.join(INVARIANTS_FILE);
+
+    if invariants.is_empty() {
+        remove_if_exists(&invariants_path)?;
+    } else {
+        let file = File::create(&invariants_path).context(format!("Unable to create {}", invariants_path.display()))?;
+        serde_json::to_writer_pretty(file, &invariants)
+            .context(format!("Unable to write {}", invariants_path.display()))?;
+    }
+
+    for worker in 0..opts.num_update_workers {
+        let worker_dir = opts.output_dir.join(format!("worker_{worker}"));
+        std::fs::create_dir_all(&worker_dir).context(format!("Unable to create {}", worker_dir.display()))?;
+
+        let mut used_subjects = Vec::new();
+        let shared_subject = NamedNode::new_unchecked(format!("{SHARED_SUBJECT_PREFIX}{worker}"));
+
+        for op in 0..opts.num_operations {
+            let fresh_subject = subjects.get(worker * opts.num_operations + op);
+            let operation = generator.next_operation(&opts.mix, fresh_subject, &used_subjects, &shared_subject);
+            used_subjects.extend(fresh_subject.cloned());
+
+            let path = worker_dir.join(format!("op_{op}.json"));
+            let file = File::create(&path).context(format!("Unable to create {}", path.display()))?;
+            serde_json::to_writer_pretty(file, &operation).context(format!("Unable to write {}", path.display()))?;
+        }
+
+        // when re-generating into an existing directory, `verify` must not pick up operations of the earlier workload
+        for op in opts.num_operations.. {
+            if !remove_if_exists(&worker_dir.join(format!("op_{op}.json")))? {
+                break;
+            }
+        }
+    }
+
+    tracing::info!(
+        "Generated {} operations for {} update workers in {}",
+        opts.num_operations,
+        opts.num_update_workers,
+        opts.output_dir.display()
+    );
+
+    Ok(())
+}
+
+/// Removes a file left behind by an earlier workload, returns whether it existed
+fn remove_if_exists(path: &Path) -> anyhow::Result<bool> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).context(format!("Unable to remove {}", path.display())),
+    }
+}
+
+/// The invariants of the shared data that the operations of the mix write to
+fn invariants(dataset: &Dataset, opts: &GenerateOpts) -> anyhow::Result<Vec<Invariant>> {
+    let uses = |kind: OperationKind| opts.mix.iter().any(|k| k.kind == kind && k.weight > 0);
+    let mut invariants = Vec::new();
+
+    if uses(OperationKind::SharedInsertData) || uses(OperationKind::SharedGspPost) {
+        invariants.push(Invariant {
+            name: SHARED_GRAPH_SIZE.to_owned(),
+            query: format!("SELECT (COUNT(*) AS ?n) WHERE {{ GRAPH {SHARED_GRAPH} {{ ?s ?p ?o }} }}"),
+            initial: dataset.graph(SHARED_GRAPH).len() as i64,
+            conserved: false,
+        });
+    }
+
+    if uses(OperationKind::SharedIncrement) {
+        for counter in 0..opts.num_counters {
+            let counter_iri = counter_iri(counter);
+
+            let initial = dataset
+                .graph(GraphNameRef::DefaultGraph)
+                .object_for_subject_predicate(&counter_iri, COUNT_PREDICATE)
+                .map_or(Some(0), integer_value)
+                .with_context(|| {
+                    format!("The dataset contains counter {counter_iri}, but its value is not an integer")
+                })?;
+
+            // a counter that does not exist yet has the value 0, several values mean that an increment was duplicated
+            invariants.push(Invariant {
+                name: counter_invariant(counter),
+                query: format!(
+                    "SELECT ?n WHERE {{ OPTIONAL {{ {counter_iri} {COUNT_PREDICATE} ?c }} BIND(COALESCE(?c, 0) AS ?n) }}"
+                ),
+                initial,
+                conserved: false,
+            });
+        }
+    }
+
+    if uses(OperationKind::Transfer) {
+        let initial = dataset
+            .graph(GraphNameRef::DefaultGraph)
+            .triples_for_predicate(BALANCE_PREDICATE)
+            .map(|t| integer_value(t.object).with_context(|| format!("The balance of {} is not an integer", t.subject)))
+            .sum::<anyhow::Result<i64>>()?;
+
+        invariants.push(Invariant {
+            name: TOTAL_BALANCE.to_owned(),
+            query: format!("SELECT (SUM(?balance) AS ?total) WHERE {{ ?account {BALANCE_PREDICATE} ?balance }}"),
+            initial,
+            conserved: true,
+        });
+    }
+
+    Ok(invariants)
+}
+
+fn integer_value(term: TermRef) -> Option<i64> {
+    match term {
+        TermRef::Literal(value) => value.value().parse().ok(),
+        _ => None,
+    }
+}
+
+fn counter_iri(counter: usize) -> NamedNode {
+    NamedNode::new_unchecked(format!("{COUNTER_PREFIX}{counter}"))
+}
+
+fn counter_invariant(counter: usize) -> String {
+    format!("counter_{counter}")
+}
+
+fn account_iri(account: usize) -> NamedNode {
+    NamedNode::new_unchecked(format!("{ACCOUNT_PREFIX}{account}"))
+}
+
+fn load_dataset(path: &Path) -> anyhow::Result<Dataset> {
+    let file = BufReader::new(File::open(path).context("Unable to open dataset")?);
+
+    let quads: Result<Dataset, _> = if path.extension().is_some_and(|ext| ext == "nq") {
+        NQuadsParser::new().for_reader(file).collect()
+    } else {
+        NTriplesParser::new()
+            .for_reader(file)
+            .map(|triple| triple.map(|t| t.in_graph(GraphNameRef::DefaultGraph)))
+            .collect()
+    };
+
+    quads.context("Unable to parse dataset")
+}
+
+/// Returns the IRI subjects of the default graph (that have the given type), sorted for reproducibility
+fn candidate_subjects(dataset: &Dataset, subject_type: Option<NamedNodeRef>) -> Vec<NamedNode> {
+    let subjects: BTreeSet<NamedNode> = dataset
+        .graph(GraphNameRef::DefaultGraph)
+        .iter()
+        .filter(|t| subject_type.is_none_or(|ty| t.predicate == rdf::TYPE && t.object == ty.into()))
+        .filter_map(|t| match t.subject {
+            NamedOrBlankNodeRef::NamedNode(n) => Some(n.into_owned()),
+            _ => None,
+        })
+        .collect();
+
+    subjects.into_iter().collect()
+}
+
+fn serialize_triples<'a>(triples: impl IntoIterator<Item = &'a Triple>) -> String {
+    triples.into_iter().map(|t| format!("{t} .\n")).collect()
+}
+
+struct WorkloadGenerator {
+    dataset: Dataset,
+    rng: StdRng,
+    next_object: usize,
+    num_counters: usize,
+    num_accounts: usize,
+}
+
+impl WorkloadGenerator {
+    /// Generates the next operation of a worker and applies it to the in-memory dataset.
+    ///
+    /// `fresh_subject` has not been touched by any operation yet, `used_subjects` are the subjects
+    /// previously used by the same worker. `shared_subject` is the subject of the worker in the shared graph.
+    /// The `fresh_subject` may only be missing if the mix does not contain operations that draw subjects.
+    fn next_operation(
+        &mut self,
+        mix: &[WeightedOperationKind],
+        fresh_subject: Option<&NamedNode>,
+        used_subjects: &[NamedNode],
+        shared_subject: &NamedNode,
+    ) -> UpdateOperation {
+        let existing_graphs: Vec<&NamedNode> = used_subjects.iter().filter(|s| self.graph_exists(s)).collect();
+
+        // only choose operations whose preconditions are met, fall back to INSERT DATA otherwise
+        let possible: Vec<&WeightedOperationKind> = mix
+            .iter()
+            .filter(|k| match k.kind {
+                OperationKind::InsertData
+                | OperationKind::GspPost
+                | OperationKind::SharedInsertData
+                | OperationKind::SharedGspPost
+                | OperationKind::SharedIncrement
+                | OperationKind::Transfer => true,
+                OperationKind::DeleteData => {
+                    fresh_subject.is_some_and(|subject| !self.deletable_triples(subject).is_empty())
+                },
+                OperationKind::GspPut => !used_subjects.is_empty(),
+                OperationKind::GspDelete => !existing_graphs.is_empty(),
+            })
+            .filter(|k| k.weight > 0)
+            .collect();
+
+        let kind = match WeightedIndex::new(possible.iter().map(|k| k.weight)) {
+            Ok(dist) => possible[dist.sample(&mut self.rng)].kind,
+            Err(_) => OperationKind::InsertData,
+        };
+
+        let fresh_subject = || fresh_subject.expect("a subject is drawn for every operation of this mix");
+
+        match kind {
+            OperationKind::InsertData => self.insert_data(fresh_subject()),
+            OperationKind::DeleteData => self.delete_data(fresh_subject()),
+            OperationKind::GspPost => self.gsp_post(fresh_subject()),
+            OperationKind::GspPut => {
+                let subject = used_subjects.choose(&mut self.rng).unwrap().clone();
+                self.gsp_put(&subject)
+            },
+            OperationKind::GspDelete => {
+                let subject = (*existing_graphs.choose(&mut self.rng).unwrap()).clone();
+                self.gsp_delete(&subject)
+            },
+            OperationKind::SharedInsertData => self.shared_insert_data(shared_subject),
+            OperationKind::SharedGspPost => self.shared_gsp_post(shared_subject),
+            OperationKind::SharedIncrement => {
+                let counter = self.rng.random_range(0..self.num_counters);
+                self.shared_increment(counter, shared_subject)
+            },
+            OperationKind::Transfer => {
+                let accounts = rand::seq::index::sample(&mut self.rng, self.num_accounts, 2);
+                let amount = self.rng.random_range(TRANSFER_AMOUNT);
+                self.transfer(accounts.index(0), accounts.index(1), amount, shared_subject)
+            },
+        }
+    }
+
+    fn graph_exists(&self, ident: &NamedNode) -> bool {
+        !self.dataset.graph(ident).is_empty()
+    }
+
+    /// Triples of `ident` in the default graph that can be used in DELETE DATA (i.e. do not contain blank nodes),
+    /// sorted for reproducibility as the order of the dataset depends on how its terms were interned
+    fn deletable_triples(&self, ident: &NamedNode) -> Vec<Triple> {
+        let mut triples: Vec<Triple> = self
+            .dataset
+            .graph(GraphNameRef::DefaultGraph)
+            .triples_for_subject(ident)
+            .filter(|t| !t.object.is_blank_node())
+            .map(|t| t.into_owned())
+            .collect();
+
+        triples.sort_unstable_by_key(ToString::to_string);
+        triples
+    }
+
+    fn fresh_triples(&mut self, subject: NamedNodeRef) -> Vec<Triple> {
+        let n = self.rng.random_range(INSERT_SIZE);
+
+        (0..n)
+            .map(|_| {
+                let object = NamedNode::new_unchecked(format!("{INSERT_OBJECT_PREFIX}{}", self.next_object));
+                self.next_object += 1;
+
+                Triple::new(subject, INSERT_PREDICATE, object)
+            })
+            .collect()
+    }
+
+    fn insert_data(&mut self, ident: &NamedNode) -> UpdateOperation {
+        let triples = self.fresh_triples(ident.as_ref());
+        let named_triples: Vec<Triple> = triples
+            .iter()
+            .map(|t| Triple::new(GRAPH_SUBJECT, t.predicate.clone(), t.object.clone()))
+            .collect();
+
+        let body = format!(
+            "INSERT DATA {{ {} }};INSERT DATA {{ GRAPH {ident} {{ {} }} }}",
+            serialize_triples(&triples),
+            serialize_triples(&named_triples)
+        );
+
+        self.dataset
+            .extend(triples.iter().map(|t| t.as_ref().in_graph(GraphNameRef::DefaultGraph)));
+        self.dataset
+            .extend(named_triples.iter().map(|t| t.as_ref().in_graph(ident)));
+
+        sparql_update(body, self.validate_default_and_named_graph(ident))
+    }
+
+    fn delete_data(&mut self, ident: &NamedNode) -> UpdateOperation {
+        let mut triples = self.deletable_triples(ident);
+        triples.shuffle(&mut self.rng);
+        triples.truncate(self.rng.random_range(DELETE_SIZE));
+
+        let body = format!("DELETE DATA {{ {} }}", serialize_triples(&triples));
+
+        for t in &triples {
+            self.dataset.remove(t.as_ref().in_graph(GraphNameRef::DefaultGraph));
+        }
+
+        sparql_update(body, self.validate_default_graph(ident))
+    }
+
+    fn gsp_post(&mut self, ident: &NamedNode) -> UpdateOperation {
+        let triples = self.fresh_triples(GRAPH_SUBJECT);
+        let body = serialize_triples(&triples);
+
+        self.dataset.extend(triples.iter().map(|t| t.as_ref().in_graph(ident)));
+
+        gsp(Method::Post, ident, body, self.validate_default_and_named_graph(ident))
+    }
+
+    fn gsp_put(&mut self, ident: &NamedNode) -> UpdateOperation {
+        let triples = self.fresh_triples(GRAPH_SUBJECT);
+        let body = serialize_triples(&triples);
+
+        self.clear_graph(ident);
+        self.dataset.extend(triples.iter().map(|t| t.as_ref().in_graph(ident)));
+
+        gsp(Method::Put, ident, body, self.validate_named_graph(ident))
+    }
+
+    fn gsp_delete(&mut self, ident: &NamedNode) -> UpdateOperation {
+        self.clear_graph(ident);
+
+        gsp(Method::Delete, ident, String::new(), self.validate_named_graph(ident))
+    }
+
+    fn shared_insert_data(&mut self, shared_subject: &NamedNode) -> UpdateOperation {
+        let triples = self.fresh_triples(shared_subject.as_ref());
+        let body = format!(
+            "INSERT DATA {{ GRAPH {SHARED_GRAPH} {{ {} }} }}",
+            serialize_triples(&triples)
+        );
+
+        self.dataset
+            .extend(triples.iter().map(|t| t.as_ref().in_graph(SHARED_GRAPH)));
+
+        UpdateOperation {
+            invariant_deltas: BTreeMap::from([(SHARED_GRAPH_SIZE.to_owned(), triples.len() as i64)]),
+            ..sparql_update(body, self.validate_shared_graph(shared_subject))
+        }
+    }
+
+    fn shared_gsp_post(&mut self, shared_subject: &NamedNode) -> UpdateOperation {
+        let triples = self.fresh_triples(shared_subject.as_ref());
+        let body = serialize_triples(&triples);
+
+        self.dataset
+            .extend(triples.iter().map(|t| t.as_ref().in_graph(SHARED_GRAPH)));
+
+        UpdateOperation {
+            invariant_deltas: BTreeMap::from([(SHARED_GRAPH_SIZE.to_owned(), triples.len() as i64)]),
+            ..gsp(
+                Method::Post,
+                &SHARED_GRAPH.into_owned(),
+                body,
+                self.validate_shared_graph(shared_subject),
+            )
+        }
+    }
+
+    /// Increments a shared counter together with the counter of the worker, which is used for validation.
+    /// Both are read and written by a single DELETE/INSERT WHERE, so concurrent increments must be isolated
+    /// from each other for the shared counter to end up with the number of acknowledged increments.
+    fn shared_increment(&mut self, counter: usize, shared_subject: &NamedNode) -> UpdateOperation {
+        let counter_iri = counter_iri(counter);
+        let body = format!(
+            "DELETE {{ {counter_iri} {COUNT_PREDICATE} ?c . {shared_subject} {COUNT_PREDICATE} ?w }}\n\
+             INSERT {{ {counter_iri} {COUNT_PREDICATE} ?c_next . {shared_subject} {COUNT_PREDICATE} ?w_next }}\n\
+             WHERE {{\n    \
+                 OPTIONAL {{ {counter_iri} {COUNT_PREDICATE} ?c }}\n    \
+                 OPTIONAL {{ {shared_subject} {COUNT_PREDICATE} ?w }}\n    \
+                 BIND(COALESCE(?c, 0) + 1 AS ?c_next)\n    \
+                 BIND(COALESCE(?w, 0) + 1 AS ?w_next)\n\
+             }}"
+        );
+
+        self.add_to_worker_total(shared_subject, COUNT_PREDICATE, 1);
+
+        UpdateOperation {
+            invariant_deltas: BTreeMap::from([(counter_invariant(counter), 1)]),
+            ..sparql_update(body, self.validate_worker_total(shared_subject, COUNT_PREDICATE))
+        }
+    }
+
+    /// Transfers money between two accounts with a single DELETE/INSERT WHERE, which also adds the amount to
+    /// the total the worker transferred, which is used for validation. The sum of all balances must never change.
+    fn transfer(&mut self, from: usize, to: usize, amount: i64, shared_subject: &NamedNode) -> UpdateOperation {
+        let (from, to) = (account_iri(from), account_iri(to));
+        let body = format!(
+            "DELETE {{ {from} {BALANCE_PREDICATE} ?from_balance . {to} {BALANCE_PREDICATE} ?to_balance . \
+                       {shared_subject} {TRANSFERRED_PREDICATE} ?transferred }}\n\
+             INSERT {{ {from} {BALANCE_PREDICATE} ?from_next . {to} {BALANCE_PREDICATE} ?to_next . \
+                       {shared_subject} {TRANSFERRED_PREDICATE} ?transferred_next }}\n\
+             WHERE {{\n    \
+                 OPTIONAL {{ {from} {BALANCE_PREDICATE} ?from_balance }}\n    \
+                 OPTIONAL {{ {to} {BALANCE_PREDICATE} ?to_balance }}\n    \
+                 OPTIONAL {{ {shared_subject} {TRANSFERRED_PREDICATE} ?transferred }}\n    \
+                 BIND(COALESCE(?from_balance, 0) - {amount} AS ?from_next)\n    \
+                 BIND(COALESCE(?to_balance, 0) + {amount} AS ?to_next)\n    \
+                 BIND(COALESCE(?transferred, 0) + {amount} AS ?transferred_next)\n\
+             }}"
+        );
+
+        self.add_to_worker_total(shared_subject, TRANSFERRED_PREDICATE, amount);
+
+        sparql_update(body, self.validate_worker_total(shared_subject, TRANSFERRED_PREDICATE))
+    }
+
+    /// Adds to an integer property of the worker in the in-memory dataset, which starts at 0 if it does not exist
+    fn add_to_worker_total(&mut self, shared_subject: &NamedNode, predicate: NamedNodeRef, amount: i64) {
+        let current = self
+            .dataset
+            .graph(GraphNameRef::DefaultGraph)
+            .object_for_subject_predicate(shared_subject, predicate)
+            .map(|value| value.into_owned());
+
+        let total = current
+            .as_ref()
+            .and_then(|value| integer_value(value.as_ref()))
+            .unwrap_or(0)
+            + amount;
+
+        if let Some(current) = current {
+            self.dataset.remove(QuadRef::new(
+                shared_subject,
+                predicate,
+                &current,
+                GraphNameRef::DefaultGraph,
+            ));
+        }
+
+        self.dataset.insert(QuadRef::new(
+            shared_subject,
+            predicate,
+            &Literal::from(total),
+            GraphNameRef::DefaultGraph,
+        ));
+    }
+
+    fn clear_graph(&mut self, ident: &NamedNode) {
+        let quads: Vec<Quad> = self
+            .dataset
+            .quads_for_graph_name(ident)
+            .map(|q| q.into_owned())
+            .collect();
+
+        for q in &quads {
+            self.dataset.remove(q);
+        }
+    }
+
+    fn default_graph_state(&self, ident: &NamedNode) -> Vec<Triple> {
+        self.dataset
+            .graph(GraphNameRef::DefaultGraph)
+            .triples_for_subject(ident)
+            .map(|t| t.into_owned())
+            .collect()
+    }
+
+    fn named_graph_state(&self, ident: &NamedNode) -> Vec<Triple> {
+        self.dataset
+            .graph(ident)
+            .triples_for_subject(GRAPH_SUBJECT)
+            .map(|t| t.into_owned())
+            .collect()
+    }
+
+    fn validate_default_graph(&self, ident: &NamedNode) -> Validate {
+        Validate {
+            source: StateSource::Query(format!(
+                "CONSTRUCT {{\n    {ident} ?p ?o\n}}\nWHERE {{\n    {ident} ?p ?o .\n}}"
+            )),
+            expected: self.default_graph_state(ident).into_iter().collect(),
+        }
+    }
+
+    fn validate_named_graph(&self, ident: &NamedNode) -> Validate {
+        Validate {
+            source: StateSource::Query(format!(
+                "CONSTRUCT {{\n    {GRAPH_SUBJECT} ?p ?o\n}}\nWHERE {{\n    GRAPH {ident} {{ {GRAPH_SUBJECT} ?p ?o }}\n}}"
+            )),
+            expected: self.named_graph_state(ident).into_iter().collect(),
+        }
+    }
+
+    /// Validates an integer property of the worker, see [`Self::add_to_worker_total`]
+    fn validate_worker_total(&self, shared_subject: &NamedNode, predicate: NamedNodeRef) -> Validate {
+        Validate {
+            source: StateSource::Query(format!(
+                "CONSTRUCT {{\n    {shared_subject} {predicate} ?total\n}}\nWHERE {{\n    {shared_subject} {predicate} ?total .\n}}"
+            )),
+            expected: self
+                .dataset
+                .graph(GraphNameRef::DefaultGraph)
+                .triples_for_subject(shared_subject)
+                .filter(|t| t.predicate == predicate)
+                .map(|t| t.into_owned())
+                .collect(),
+        }
+    }
+
+    /// Validates the part of the shared graph that belongs to a single worker
+    fn validate_shared_graph(&self, shared_subject: &NamedNode) -> Validate {
+        Validate {
+            source: StateSource::Query(format!(
+                "CONSTRUCT {{\n    {shared_subject} ?p ?o\n}}\nWHERE {{\n    GRAPH {SHARED_GRAPH} {{ {shared_subject} ?p ?o }}\n}}"
+            )),
+            expected: self
+                .dataset
+                .graph(SHARED_GRAPH)
+                .triples_for_subject(shared_subject)
+                .map(|t| t.into_owned())
+                .collect(),
+        }
+    }
+
+    fn validate_default_and_named_graph(&self, ident: &NamedNode) -> Validate {
+        Validate {
+            source: StateSource::Query(format!(
+                "CONSTRUCT {{\n    {ident} ?pd ?od .\n    {GRAPH_SUBJECT} ?pn ?on .\n}}\nWHERE {{\n    {{ {ident} ?pd ?od }}\n    UNION\n    {{ GRAPH {ident} {{ {GRAPH_SUBJECT} ?pn ?on }} }}\n}}"
+            )),
+            expected: self.default_graph_state(ident).into_iter().chain(self.named_graph_state(ident)).collect(),
+        }
+    }
+}
+
+fn sparql_update(body: String, validate: Validate) -> UpdateOperation {
+    UpdateOperation::single(
+        Request {
+            endpoint: Endpoint::Update,
+            query_params: HashMap::new(),
+            headers: HashMap::from([("Content-type".to_owned(), "application/sparql-update".to_owned())]),
+            method: Method::Post,
+            body,
+            expect: Expect::Success,
+        },
+        validate,
+    )
+}
+
+fn gsp(method: Method, ident: &NamedNode, body: String, validate: Validate) -> UpdateOperation {
+    let headers = match method {
+        Method::Delete => HashMap::new(),
+        _ => HashMap::from([("Content-type".to_owned(), "application/n-triples".to_owned())]),
+    };
+
+    UpdateOperation::single(
+        Request {
+            endpoint: Endpoint::Gsp,
+            query_params: HashMap::from([("graph".to_owned(), ident.as_str().to_owned())]),
+            headers,
+            method,
+            body,
+            expect: Expect::Success,
+        },
+        validate,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db_state::DbState, operation::Requests};
+    use oxrdf::{GraphName, NamedOrBlankNode, Term, Variable};
+    use spargebra::{
+        algebra::GraphPattern,
+        term::{GroundTerm, NamedNodePattern, TermPattern, TriplePattern},
+        GraphUpdateOperation, SparqlParser,
+    };
+    use std::collections::BTreeMap;
+
+    /// The workload generated from [`seed_dataset`] with [`opts`], which detects unintended changes of the generated
+    /// workloads. After intentional changes to the generator, run the test with `UPDATE_GOLDEN=1` to regenerate it
+    /// and review the diff. Whether the workloads are correct is checked by replaying them instead.
+    const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/generate_seed_42");
+
+    const NUM_WORKERS: usize = 3;
+    const NUM_OPERATIONS: usize = 6;
+
+    /// A directory containing the seed dataset `seed.nt`, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str, seed_dataset: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("sparql-transactional-test-{name}-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("seed.nt"), seed_dataset).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn seed_dataset() -> String {
+        (0..40)
+            .map(|i| {
+                format!(
+                    "<http://example.org/s{i}> <http://example.org/p> <http://example.org/o{i}> .\n\
+                     <http://example.org/s{i}> <http://example.org/n> \"0{i}\"^^<http://www.w3.org/2001/XMLSchema#integer> .\n\
+                     <http://example.org/s{i}> <http://example.org/b> _:b{i} .\n"
+                )
+            })
+            .collect()
+    }
+
+    fn opts(dir: &Path, output_dir: PathBuf, num_operations: usize) -> GenerateOpts {
+        let kinds = [
+            OperationKind::InsertData,
+            OperationKind::DeleteData,
+            OperationKind::GspPost,
+            OperationKind::GspPut,
+            OperationKind::GspDelete,
+            OperationKind::SharedInsertData,
+            OperationKind::SharedIncrement,
+            OperationKind::Transfer,
+        ];
+
+        GenerateOpts {
+            num_update_workers: NUM_WORKERS,
+            num_operations,
+            mix: kinds
+                .into_iter()
+                .map(|kind| WeightedOperationKind { kind, weight: 1 })
+                .collect(),
+            subject_type: None,
+            num_counters: 1,
+            num_accounts: 10,
+            seed: Some(42),
+            dataset: dir.join("seed.nt"),
+            output_dir,
+        }
+    }
+
+    fn load_operation(path: &Path) -> (Request, Validate) {
+        let operation: UpdateOperation = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let Requests::Single(request) = operation.requests else {
+            panic!("{} contains several requests", path.display());
+        };
+
+        (request, operation.validate)
+    }
+
+    /// Applies a generated request to the dataset, returns the subjects and graphs it touched
+    fn apply(dataset: &mut Dataset, request: &Request) -> Vec<String> {
+        match request.endpoint {
+            Endpoint::Update => {
+                let mut touched = Vec::new();
+
+                for operation in SparqlParser::new().parse_update(&request.body).unwrap().operations {
+                    match operation {
+                        GraphUpdateOperation::InsertData { data } => {
+                            for q in data {
+                                let graph_name = match q.graph_name {
+                                    spargebra::term::GraphName::NamedNode(g) => GraphName::NamedNode(g),
+                                    spargebra::term::GraphName::DefaultGraph => GraphName::DefaultGraph,
+                                };
+                                touched.push(match &graph_name {
+                                    GraphName::NamedNode(g) => g.as_str().to_owned(),
+                                    _ => q.subject.to_string(),
+                                });
+                                dataset.insert(&Quad::new(q.subject, q.predicate, q.object, graph_name));
+                            }
+                        },
+                        GraphUpdateOperation::DeleteData { data } => {
+                            for q in data {
+                                let object: Term = match q.object {
+                                    GroundTerm::NamedNode(n) => n.into(),
+                                    GroundTerm::Literal(l) => l.into(),
+                                };
+                                assert!(matches!(q.graph_name, spargebra::term::GraphName::DefaultGraph));
+                                touched.push(q.subject.to_string());
+                                dataset.remove(&Quad::new(q.subject, q.predicate, object, GraphName::DefaultGraph));
+                            }
+                        },
+                        operation => panic!("Unexpected update operation {operation}"),
+                    }
+                }
+
+                touched
+            },
+            Endpoint::Gsp => {
+                let graph = NamedNode::new(&request.query_params["graph"]).unwrap();
+
+                if !matches!(request.method, Method::Post) {
+                    let quads: Vec<Quad> = dataset.quads_for_graph_name(&graph).map(|q| q.into_owned()).collect();
+                    for q in &quads {
+                        dataset.remove(q);
+                    }
+                }
+
+                for triple in NTriplesParser::new().for_slice(&request.body) {
+                    dataset.insert(&triple.unwrap().in_graph(graph.clone()));
+                }
+
+                vec![graph.as_str().to_owned()]
+            },
+        }
+    }
+
+    fn matches(pattern: &TermPattern, term: &Term, solution: &mut HashMap<Variable, Term>) -> bool {
+        match pattern {
+            TermPattern::NamedNode(n) => matches!(term, Term::NamedNode(t) if t == n),
+            TermPattern::BlankNode(b) => matches!(term, Term::BlankNode(t) if t == b),
+            TermPattern::Literal(l) => matches!(term, Term::Literal(t) if t == l),
+            TermPattern::Variable(v) => match solution.get(v) {
+                Some(bound) => bound == term,
+                None => {
+                    solution.insert(v.clone(), term.clone());
+                    true
+                },
+            },
+        }
+    }
+
+    /// Evaluates the triple patterns, `GRAPH` blocks and unions of the generated validation queries
+    fn evaluate(pattern: &GraphPattern, graph: &GraphName, dataset: &Dataset) -> Vec<HashMap<Variable, Term>> {
+        match pattern {
+            GraphPattern::Bgp { patterns } => {
+                let [TriplePattern { subject, predicate, object }] = patterns.as_slice() else {
+                    panic!("Unexpected basic graph pattern {pattern}");
+                };
+
+                dataset
+                    .quads_for_graph_name(graph)
+                    .filter_map(|q| {
+                        let mut solution = HashMap::new();
+                        let predicate = match predicate {
+                            NamedNodePattern::NamedNode(n) => TermPattern::NamedNode(n.clone()),
+                            NamedNodePattern::Variable(v) => TermPattern::Variable(v.clone()),
+                        };
+
+                        let is_match = matches(subject, &Term::from(q.subject.into_owned()), &mut solution)
+                            && matches(&predicate, &Term::from(q.predicate.into_owned()), &mut solution)
+                            && matches(object, &q.object.into_owned(), &mut solution);
+                        is_match.then_some(solution)
+                    })
+                    .collect()
+            },
+            GraphPattern::Graph { name: NamedNodePattern::NamedNode(name), inner } => {
+                evaluate(inner, &GraphName::NamedNode(name.clone()), dataset)
+            },
+            GraphPattern::Project { inner, .. } => evaluate(inner, graph, dataset),
+            GraphPattern::Union { left, right } => {
+                let mut solutions = evaluate(left, graph, dataset);
+                solutions.extend(evaluate(right, graph, dataset));
+                solutions
+            },
+            _ => panic!("Unexpected graph pattern {pattern}"),
+        }
+    }
+
+    fn instantiate(pattern: &TermPattern, solution: &HashMap<Variable, Term>) -> Option<Term> {
+        match pattern {
+            TermPattern::NamedNode(n) => Some(n.clone().into()),
+            TermPattern::Variable(v) => solution.get(v).cloned(),
+            _ => panic!("Unexpected template term {pattern}"),
+        }
+    }
+
+    /// Reads the state that a generated validation query returns on the dataset
+    fn read_state(validate: &Validate, dataset: &Dataset) -> DbState {
+        let StateSource::Query(query) = &validate.source else {
+            panic!("Unexpected state source {}", validate.source);
+        };
+        let spargebra::Query::Construct { template, pattern, .. } = SparqlParser::new().parse_query(query).unwrap()
+        else {
+            panic!("Unexpected validation query {query}");
+        };
+
+        evaluate(&pattern, &GraphName::DefaultGraph, dataset)
+            .iter()
+            .flat_map(|solution| {
+                template.iter().filter_map(move |t| {
+                    let subject = match instantiate(&t.subject, solution)? {
+                        Term::NamedNode(n) => NamedOrBlankNode::from(n),
+                        Term::BlankNode(b) => b.into(),
+                        term => panic!("Invalid subject {term}"),
+                    };
+                    let predicate = match instantiate(&t.predicate.clone().into(), solution)? {
+                        Term::NamedNode(n) => n,
+                        term => panic!("Invalid predicate {term}"),
+                    };
+                    Some(Triple::new(subject, predicate, instantiate(&t.object, solution)?))
+                })
+            })
+            .collect()
+    }
+
+    /// The JSON files of a workload, keyed by their path relative to the workload directory
+    fn workload_files(dir: &Path) -> BTreeMap<PathBuf, serde_json::Value> {
+        let mut files = BTreeMap::new();
+        let mut dirs = vec![dir.to_owned()];
+
+        while let Some(current) = dirs.pop() {
+            for entry in std::fs::read_dir(&current).unwrap() {
+                let path = entry.unwrap().path();
+
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    let value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+                    files.insert(path.strip_prefix(dir).unwrap().to_owned(), value);
+                }
+            }
+        }
+
+        files
+    }
+
+    /// Replays the operations that work on subjects of their own, which the replay supports
+    #[test]
+    fn expected_states_match_replayed_operations() {
+        let dir = TempDir::new("replay", &seed_dataset());
+
+        let replay_opts = GenerateOpts {
+            mix: [
+                OperationKind::InsertData,
+                OperationKind::DeleteData,
+                OperationKind::GspPost,
+                OperationKind::GspPut,
+                OperationKind::GspDelete,
+            ]
+            .into_iter()
+            .map(|kind| WeightedOperationKind { kind, weight: 1 })
+            .collect(),
+            ..opts(&dir, dir.join("workload"), NUM_OPERATIONS)
+        };
+        generate(&replay_opts).unwrap();
+
+        let mut dataset = load_dataset(&dir.join("seed.nt")).unwrap();
+        let mut touched_by: HashMap<String, usize> = HashMap::new();
+
+        for worker in 0..NUM_WORKERS {
+            let worker_dir = dir.join("workload").join(format!("worker_{worker}"));
+
+            for op in 0..NUM_OPERATIONS {
+                let path = worker_dir.join(format!("op_{op}.json"));
+                let (request, validate) = load_operation(&path);
+
+                for resource in apply(&mut dataset, &request) {
+                    let other = *touched_by.entry(resource.clone()).or_insert(worker);
+                    assert_eq!(other, worker, "{resource} is used by workers {other} and {worker}");
+                }
+
+                assert_eq!(
+                    read_state(&validate, &dataset),
+                    validate.expected,
+                    "Unexpected state after {}",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn workload_matches_golden_files() {
+        let dir = TempDir::new("generate", &seed_dataset());
+
+        // re-generating a shorter workload into the same directory must replace the earlier one
+        generate(&opts(&dir, dir.join("workload"), NUM_OPERATIONS + 2)).unwrap();
+        generate(&opts(&dir, dir.join("workload"), NUM_OPERATIONS)).unwrap();
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            let _ = std::fs::remove_dir_all(GOLDEN_DIR);
+            generate(&opts(&dir, PathBuf::from(GOLDEN_DIR), NUM_OPERATIONS)).unwrap();
+        }
+
+        let actual = workload_files(&dir.join("workload"));
+        let expected = workload_files(Path::new(GOLDEN_DIR));
+
+        assert_eq!(actual.keys().collect::<Vec<_>>(), expected.keys().collect::<Vec<_>>());
+
+        for (path, value) in &actual {
+            assert_eq!(
+                value,
+                &expected[path],
+                "{} differs from the golden file",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn shared_operations_do_not_need_subjects() {
+        let dir = TempDir::new("shared", "");
+
+        let shared_opts = GenerateOpts {
+            mix: vec![
+                WeightedOperationKind { kind: OperationKind::SharedGspPost, weight: 1 },
+                WeightedOperationKind { kind: OperationKind::Transfer, weight: 1 },
+                WeightedOperationKind { kind: OperationKind::InsertData, weight: 0 },
+            ],
+            ..opts(&dir, dir.join("workload"), NUM_OPERATIONS)
+        };
+        generate(&shared_opts).unwrap();
+
+        let files = workload_files(&dir.join("workload"));
+        assert_eq!(files.len(), NUM_WORKERS * NUM_OPERATIONS + 1);
+
+        let per_worker_opts = GenerateOpts {
+            mix: vec![WeightedOperationKind { kind: OperationKind::InsertData, weight: 1 }],
+            ..opts(&dir, dir.join("workload"), NUM_OPERATIONS)
+        };
+        assert!(generate(&per_worker_opts).is_err());
+    }
+}
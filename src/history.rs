@@ -0,0 +1,858 @@
+use crate::{db_state::DbState, operation::StateSource};
+use anyhow::Context;
+use serde::Serialize;
+use spargebra::{
+    algebra::{AggregateExpression, Expression, GraphPattern, OrderExpression},
+    term::{NamedNodePattern, TermPattern},
+    SparqlParser,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt::{Display, Formatter},
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Read,
+    Write,
+}
+
+/// A single read or write of the state of a validation source, from its invocation until its completion
+#[derive(Debug)]
+struct Event {
+    process: Arc<str>,
+    kind: EventKind,
+    /// The validation source, i.e. the part of the database that is read or written
+    source: StateSource,
+    /// The observed state of a read, the expected state after a write
+    value: DbState,
+    invoked: Duration,
+    /// `None` if it is unknown whether a write took effect
+    completed: Option<Duration>,
+}
+
+/// An event as written to the history file
+#[derive(Debug, Clone, Serialize)]
+pub struct EventRepr {
+    pub process: String,
+    pub kind: EventKind,
+    pub key: String,
+    pub value: DbState,
+    pub invoked_ms: f64,
+    pub completed_ms: Option<f64>,
+}
+
+impl From<&Event> for EventRepr {
+    fn from(event: &Event) -> Self {
+        Self {
+            process: event.process.to_string(),
+            kind: event.kind,
+            key: event.source.to_string(),
+            value: event.value.clone(),
+            invoked_ms: event.invoked.as_secs_f64() * 1000.0,
+            completed_ms: event.completed.map(|completed| completed.as_secs_f64() * 1000.0),
+        }
+    }
+}
+
+/// The history of all updates and state reads of a `verify` run
+#[derive(Clone)]
+pub struct History {
+    start: Instant,
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+/// Records the events of a single process (i.e. worker) into a history
+#[derive(Clone)]
+pub struct HistoryRecorder {
+    history: History,
+    process: Arc<str>,
+}
+
+impl HistoryRecorder {
+    /// The current time, to be used as the invocation or completion time of an event
+    pub fn now(&self) -> Duration {
+        self.history.start.elapsed()
+    }
+
+    pub fn record(
+        &self,
+        kind: EventKind,
+        source: &StateSource,
+        value: &DbState,
+        invoked: Duration,
+        completed: Option<Duration>,
+    ) {
+        let event = Event {
+            process: self.process.clone(),
+            kind,
+            source: source.clone(),
+            value: value.clone(),
+            invoked,
+            completed,
+        };
+
+        self.history.events.lock().unwrap().push(event);
+    }
+}
+
+/// The result of checking a history for linearizability
+#[derive(Debug, Serialize)]
+pub struct LinearizabilityReport {
+    pub n_events: usize,
+    pub n_keys: usize,
+    pub violations: Vec<LinearizabilityViolation>,
+    /// Validation sources whose check was given up because it exceeded the search budget
+    pub inconclusive: Vec<InconclusiveCheck>,
+}
+
+/// A validation source whose reads and writes cannot be put into a single order that respects real time
+#[derive(Debug, Serialize)]
+pub struct LinearizabilityViolation {
+    pub key: String,
+    pub n_events: usize,
+    /// The event at which the longest consistent order found could not be extended
+    pub event: Option<EventRepr>,
+}
+
+impl Display for LinearizabilityViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The history of a validation source ({} events) is not linearizable",
+            self.n_events
+        )?;
+
+        if let Some(event) = &self.event {
+            let kind = match event.kind {
+                EventKind::Read => "read",
+                EventKind::Write => "write",
+            };
+
+            write!(
+                f,
+                ", the {kind} of {} invoked at {:.3}ms",
+                event.process, event.invoked_ms
+            )?;
+
+            if let Some(completed_ms) = event.completed_ms {
+                write!(f, " and completed at {completed_ms:.3}ms")?;
+            }
+
+            write!(f, " cannot be ordered consistently with the other events")?;
+        }
+
+        write!(f, "\nValidation source:\n{}", self.key)?;
+
+        if let Some(event) = &self.event {
+            write!(f, "\nValue:\n{}", event.value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A validation source whose history could not be checked within [`MAX_SEARCH_STATES`]
+#[derive(Debug, Serialize)]
+pub struct InconclusiveCheck {
+    pub key: String,
+    pub n_events: usize,
+}
+
+impl Display for InconclusiveCheck {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The linearizability check of a validation source ({} events) is inconclusive, \
+             it exceeded the budget of {MAX_SEARCH_STATES} search states\nValidation source:\n{}",
+            self.n_events, self.key
+        )
+    }
+}
+
+/// The number of distinct search states after which the check of a single validation source is given up
+const MAX_SEARCH_STATES: usize = 1_000_000;
+
+impl History {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self { start: Instant::now(), events: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    pub fn recorder(&self, process: impl Into<Arc<str>>) -> HistoryRecorder {
+        HistoryRecorder { history: self.clone(), process: process.into() }
+    }
+
+    /// Writes the history as JSON lines, ordered by invocation time
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut events: Vec<EventRepr> = self.events.lock().unwrap().iter().map(EventRepr::from).collect();
+        events.sort_by(|a, b| a.invoked_ms.total_cmp(&b.invoked_ms));
+
+        let mut writer = BufWriter::new(File::create(path).context("Unable to create history file")?);
+
+        for event in events {
+            serde_json::to_writer(&mut writer, &event).context("Unable to write history")?;
+            writeln!(writer).context("Unable to write history")?;
+        }
+
+        writer.flush().context("Unable to write history")
+    }
+
+    /// Checks that the history of every validation source is linearizable, i.e. that there is a single order
+    /// of its reads and writes that respects real time and in which every read observes the latest write.
+    ///
+    /// The state of a source before its first write is whatever is read first. Writes to a different source
+    /// may change the data of a source as well, so they make its state unknown again:
+    /// - every write of an update worker to another source, if the worker also writes the source
+    /// - every write of another update worker to a source that may read the same data, i.e. the same subject
+    ///   in the same graph (see [`resources`]). This assumes that an update only changes the data its
+    ///   validation source reads.
+    ///
+    /// Sources whose check exceeds [`MAX_SEARCH_STATES`] are reported as inconclusive.
+    pub fn check(&self) -> LinearizabilityReport {
+        let events = self.events.lock().unwrap();
+        let keys: Vec<String> = events.iter().map(|event| event.source.to_string()).collect();
+
+        let mut ops_per_key: BTreeMap<&str, Vec<Op>> = BTreeMap::new();
+        let mut resources_per_key: BTreeMap<&str, Vec<Resource>> = BTreeMap::new();
+        for (idx, (event, key)) in events.iter().zip(&keys).enumerate() {
+            ops_per_key.entry(key).or_default().push(Op {
+                event: Some(idx),
+                invoked: event.invoked,
+                completed: event.completed,
+            });
+            resources_per_key.entry(key).or_insert_with(|| resources(&event.source));
+        }
+
+        for (key, op) in unknown_state_ops(&events, &keys, &resources_per_key) {
+            if let Some(ops) = ops_per_key.get_mut(key) {
+                ops.push(op);
+            }
+        }
+
+        let n_keys = ops_per_key.len();
+        let mut violations = Vec::new();
+        let mut inconclusive = Vec::new();
+
+        for (key, ops) in ops_per_key {
+            let n_events = ops.iter().filter(|op| op.event.is_some()).count();
+
+            match check_key(&events, &ops, MAX_SEARCH_STATES) {
+                Ok(()) => (),
+                Err(CheckError::NotLinearizable { stuck_at }) => violations.push(LinearizabilityViolation {
+                    key: key.to_owned(),
+                    n_events,
+                    event: stuck_at
+                        .and_then(|op| ops[op].event)
+                        .map(|idx| EventRepr::from(&events[idx])),
+                }),
+                Err(CheckError::Inconclusive) => inconclusive.push(InconclusiveCheck { key: key.to_owned(), n_events }),
+            }
+        }
+
+        LinearizabilityReport { n_events: events.len(), n_keys, violations, inconclusive }
+    }
+}
+
+/// A part of the database a validation source reads: the triples of a subject (`None` = any subject)
+/// in a graph (`None` = the default graph, which may be the union of all graphs, or any named graph)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Resource {
+    graph: Option<String>,
+    subject: Option<String>,
+}
+
+impl Resource {
+    const ANY: Self = Self { graph: None, subject: None };
+
+    fn overlaps(&self, other: &Self) -> bool {
+        fn overlap(a: &Option<String>, b: &Option<String>) -> bool {
+            a.is_none() || b.is_none() || a == b
+        }
+
+        overlap(&self.graph, &other.graph) && overlap(&self.subject, &other.subject)
+    }
+}
+
+/// The parts of the database a validation source (possibly) reads.
+/// Queries that cannot be analyzed are assumed to read everything.
+fn resources(source: &StateSource) -> Vec<Resource> {
+    match source {
+        StateSource::Query(query) | StateSource::DatasetQuery(query) => match SparqlParser::new().parse_query(query) {
+            Ok(query) => query_resources(&query),
+            Err(_) => vec![Resource::ANY],
+        },
+        StateSource::Graphs(graphs) => graphs
+            .iter()
+            .map(|graph| Resource { graph: Some(graph.clone()), subject: None })
+            .collect(),
+        StateSource::GspGraph(graph) => vec![Resource { graph: Some(graph.clone()), subject: None }],
+    }
+}
+
+fn query_resources(query: &spargebra::Query) -> Vec<Resource> {
+    let (dataset, pattern) = match query {
+        spargebra::Query::Select { dataset, pattern, .. }
+        | spargebra::Query::Construct { dataset, pattern, .. }
+        | spargebra::Query::Ask { dataset, pattern, .. } => (dataset, pattern),
+        // the description of a resource is up to the triplestore
+        spargebra::Query::Describe { .. } => return vec![Resource::ANY],
+    };
+
+    let default_graphs: Vec<Option<&str>> = match dataset {
+        Some(dataset) if !dataset.default.is_empty() => dataset.default.iter().map(|g| Some(g.as_str())).collect(),
+        _ => vec![None],
+    };
+    let named_graphs: Vec<Option<&str>> = match dataset.as_ref().and_then(|dataset| dataset.named.as_ref()) {
+        Some(named) => named.iter().map(|g| Some(g.as_str())).collect(),
+        None => vec![None],
+    };
+
+    let mut collector = ResourceCollector { named_graphs: &named_graphs, resources: BTreeSet::new() };
+    collector.pattern(pattern, &default_graphs);
+    collector.resources.into_iter().collect()
+}
+
+/// Collects the subjects and graphs the triple patterns of a query match
+struct ResourceCollector<'a> {
+    /// The graphs a `GRAPH ?var` pattern matches
+    named_graphs: &'a [Option<&'a str>],
+    resources: BTreeSet<Resource>,
+}
+
+impl ResourceCollector<'_> {
+    fn pattern(&mut self, pattern: &GraphPattern, graphs: &[Option<&str>]) {
+        match pattern {
+            GraphPattern::Bgp { patterns } => {
+                for triple in patterns {
+                    self.subject(&triple.subject, graphs);
+                }
+            },
+            GraphPattern::Path { subject, .. } => self.subject(subject, graphs),
+            GraphPattern::Graph { name: NamedNodePattern::NamedNode(name), inner } => {
+                self.pattern(inner, &[Some(name.as_str())])
+            },
+            GraphPattern::Graph { name: NamedNodePattern::Variable(_), inner } => {
+                self.pattern(inner, self.named_graphs)
+            },
+            GraphPattern::Join { left, right }
+            | GraphPattern::Union { left, right }
+            | GraphPattern::Minus { left, right } => {
+                self.pattern(left, graphs);
+                self.pattern(right, graphs);
+            },
+            GraphPattern::LeftJoin { left, right, expression } => {
+                self.pattern(left, graphs);
+                self.pattern(right, graphs);
+                if let Some(expression) = expression {
+                    self.expression(expression, graphs);
+                }
+            },
+            GraphPattern::Filter { expr: expression, inner } | GraphPattern::Extend { inner, expression, .. } => {
+                self.pattern(inner, graphs);
+                self.expression(expression, graphs);
+            },
+            GraphPattern::OrderBy { inner, expression } => {
+                self.pattern(inner, graphs);
+                for order in expression {
+                    let (OrderExpression::Asc(expression) | OrderExpression::Desc(expression)) = order;
+                    self.expression(expression, graphs);
+                }
+            },
+            GraphPattern::Group { inner, aggregates, .. } => {
+                self.pattern(inner, graphs);
+                for (_, aggregate) in aggregates {
+                    if let AggregateExpression::FunctionCall { expr, .. } = aggregate {
+                        self.expression(expr, graphs);
+                    }
+                }
+            },
+            GraphPattern::Project { inner, .. }
+            | GraphPattern::Distinct { inner }
+            | GraphPattern::Reduced { inner }
+            | GraphPattern::Slice { inner, .. } => self.pattern(inner, graphs),
+            // a service is evaluated by another endpoint
+            GraphPattern::Values { .. } | GraphPattern::Service { .. } => (),
+        }
+    }
+
+    /// Only `EXISTS` reads data, the rest of the expression is only searched for it
+    fn expression(&mut self, expression: &Expression, graphs: &[Option<&str>]) {
+        match expression {
+            Expression::Exists(pattern) => self.pattern(pattern, graphs),
+            Expression::NamedNode(_) | Expression::Literal(_) | Expression::Variable(_) | Expression::Bound(_) => (),
+            Expression::UnaryPlus(a) | Expression::UnaryMinus(a) | Expression::Not(a) => self.expression(a, graphs),
+            Expression::Or(a, b)
+            | Expression::And(a, b)
+            | Expression::Equal(a, b)
+            | Expression::SameTerm(a, b)
+            | Expression::Greater(a, b)
+            | Expression::GreaterOrEqual(a, b)
+            | Expression::Less(a, b)
+            | Expression::LessOrEqual(a, b)
+            | Expression::Add(a, b)
+            | Expression::Subtract(a, b)
+            | Expression::Multiply(a, b)
+            | Expression::Divide(a, b) => {
+                self.expression(a, graphs);
+                self.expression(b, graphs);
+            },
+            Expression::If(a, b, c) => {
+                self.expression(a, graphs);
+                self.expression(b, graphs);
+                self.expression(c, graphs);
+            },
+            Expression::In(a, list) => {
+                self.expression(a, graphs);
+                for b in list {
+                    self.expression(b, graphs);
+                }
+            },
+            Expression::Coalesce(list) | Expression::FunctionCall(_, list) => {
+                for a in list {
+                    self.expression(a, graphs);
+                }
+            },
+        }
+    }
+
+    fn subject(&mut self, subject: &TermPattern, graphs: &[Option<&str>]) {
+        let subject = match subject {
+            TermPattern::NamedNode(subject) => Some(subject.as_str().to_owned()),
+            _ => None,
+        };
+
+        for graph in graphs {
+            self.resources
+                .insert(Resource { graph: graph.map(str::to_owned), subject: subject.clone() });
+        }
+    }
+}
+
+/// A read or write of an event, or (without an event) an unknown change of the state
+struct Op {
+    event: Option<usize>,
+    invoked: Duration,
+    completed: Option<Duration>,
+}
+
+/// The ops that make the state of a source unknown because of writes to other sources (see [`History::check`]).
+///
+/// For every source `K` an update worker writes, the writes of that worker to other sources between two of its
+/// events on `K` are merged into a single op. Writes of other workers that may overlap `K` are an op each.
+fn unknown_state_ops<'a>(
+    events: &[Event],
+    keys: &'a [String],
+    resources_per_key: &BTreeMap<&'a str, Vec<Resource>>,
+) -> Vec<(&'a str, Op)> {
+    let mut events_per_process: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (idx, event) in events.iter().enumerate() {
+        events_per_process.entry(&event.process).or_default().push(idx);
+    }
+
+    let mut ops = Vec::new();
+    let mut written_keys_per_process: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+
+    for (process, process_events) in events_per_process.iter_mut() {
+        process_events.sort_by_key(|&idx| events[idx].invoked);
+
+        let written_keys = written_keys_per_process.entry(process).or_default();
+        written_keys.extend(
+            process_events
+                .iter()
+                .filter(|&&idx| events[idx].kind == EventKind::Write)
+                .map(|&idx| keys[idx].as_str()),
+        );
+
+        for &key in written_keys.iter() {
+            let mut run: Option<Op> = None;
+
+            for &idx in process_events.iter() {
+                let event = &events[idx];
+
+                if keys[idx] == key {
+                    ops.extend(run.take().map(|op| (key, op)));
+                } else if event.kind == EventKind::Write {
+                    let op =
+                        run.get_or_insert(Op { event: None, invoked: event.invoked, completed: Some(event.invoked) });
+                    op.completed = op.completed.zip(event.completed).map(|(a, b)| a.max(b));
+                }
+            }
+
+            ops.extend(run.map(|op| (key, op)));
+        }
+    }
+
+    for (&key, key_resources) in resources_per_key {
+        for (idx, event) in events.iter().enumerate() {
+            let other_key = keys[idx].as_str();
+
+            // writes of workers that also write `key` are already covered above
+            if event.kind != EventKind::Write
+                || other_key == key
+                || written_keys_per_process[&*event.process].contains(key)
+            {
+                continue;
+            }
+
+            let overlaps = resources_per_key[other_key]
+                .iter()
+                .any(|resource| key_resources.iter().any(|other| resource.overlaps(other)));
+
+            if overlaps {
+                ops.push((
+                    key,
+                    Op { event: None, invoked: event.invoked, completed: event.completed },
+                ));
+            }
+        }
+    }
+
+    ops
+}
+
+struct Entry {
+    op: usize,
+    is_call: bool,
+    time: Duration,
+}
+
+/// Why the ops of a source could not be linearized
+#[derive(Debug, PartialEq, Eq)]
+enum CheckError {
+    /// There is no linearization, the longest one found got stuck at this op
+    NotLinearizable { stuck_at: Option<usize> },
+    /// The search visited more than the given number of states
+    Inconclusive,
+}
+
+/// Checks that the ops of a single source are linearizable using the algorithm of Wing & Gong with the
+/// improvements of Lowe. Ops that never completed may take effect at any time after their invocation, or never.
+///
+/// Gives up after visiting `max_states` distinct states (sets of linearized ops and resulting state).
+fn check_key(events: &[Event], ops: &[Op], max_states: usize) -> Result<(), CheckError> {
+    // states are compared by index into this list, so they can be cached cheaply
+    let mut values: Vec<&DbState> = Vec::new();
+    let value_ids: Vec<Option<usize>> = ops
+        .iter()
+        .map(|op| {
+            op.event.map(|idx| {
+                let value = &events[idx].value;
+                values.iter().position(|v| *v == value).unwrap_or_else(|| {
+                    values.push(value);
+                    values.len() - 1
+                })
+            })
+        })
+        .collect();
+
+    // applies an op to the state (`None` = unknown), returns `None` if the op is not possible in that state
+    let step = |state: Option<usize>, op: usize| -> Option<Option<usize>> {
+        match (ops[op].event.map(|idx| events[idx].kind), value_ids[op]) {
+            (None, _) => Some(None),
+            (Some(EventKind::Write), value) => Some(value),
+            (Some(EventKind::Read), value) if state.is_none() || state == value => Some(value),
+            (Some(EventKind::Read), _) => None,
+        }
+    };
+
+    if ops.is_empty() {
+        return Ok(());
+    }
+
+    let mut entries: Vec<Entry> = Vec::with_capacity(2 * ops.len());
+    for (idx, op) in ops.iter().enumerate() {
+        entries.push(Entry { op: idx, is_call: true, time: op.invoked });
+        if let Some(completed) = op.completed {
+            entries.push(Entry { op: idx, is_call: false, time: completed });
+        }
+    }
+
+    // ops that are invoked at the same time another one completes are considered concurrent
+    entries.sort_by(|a, b| a.time.cmp(&b.time).then(b.is_call.cmp(&a.is_call)));
+
+    let mut return_entry: Vec<Option<usize>> = vec![None; ops.len()];
+    for (idx, entry) in entries.iter().enumerate() {
+        if !entry.is_call {
+            return_entry[entry.op] = Some(idx);
+        }
+    }
+
+    // doubly linked list of the entries that are not linearized yet, `head` is a sentinel
+    let head = entries.len();
+    let mut next: Vec<Option<usize>> = (1..=entries.len()).map(Some).chain([Some(0)]).collect();
+    next[head - 1] = None;
+    let mut prev: Vec<usize> = [head].into_iter().chain(0..entries.len()).collect();
+    prev.truncate(entries.len());
+    prev.push(head);
+
+    let unlink = |next: &mut Vec<Option<usize>>, prev: &mut Vec<usize>, idx: usize| {
+        next[prev[idx]] = next[idx];
+        if let Some(n) = next[idx] {
+            prev[n] = prev[idx];
+        }
+    };
+    let relink = |next: &mut Vec<Option<usize>>, prev: &mut Vec<usize>, idx: usize| {
+        next[prev[idx]] = Some(idx);
+        if let Some(n) = next[idx] {
+            prev[n] = idx;
+        }
+    };
+
+    let mut remaining = ops.iter().filter(|op| op.completed.is_some()).count();
+    let mut linearized = vec![0u64; ops.len().div_ceil(64)];
+    let mut state: Option<usize> = None;
+    let mut stack: Vec<(usize, Option<usize>)> = Vec::new();
+    let mut cache: HashSet<(Vec<u64>, Option<usize>)> = HashSet::new();
+    let mut stuck_at: (usize, Option<usize>) = (0, None);
+    let mut cursor = next[head];
+
+    while remaining > 0 {
+        match cursor {
+            Some(idx) if entries[idx].is_call => {
+                let op = entries[idx].op;
+
+                if let Some(new_state) = step(state, op) {
+                    linearized[op / 64] |= 1 << (op % 64);
+
+                    if cache.insert((linearized.clone(), new_state)) {
+                        if cache.len() > max_states {
+                            return Err(CheckError::Inconclusive);
+                        }
+
+                        stack.push((idx, state));
+                        state = new_state;
+
+                        unlink(&mut next, &mut prev, idx);
+                        if let Some(ret) = return_entry[op] {
+                            unlink(&mut next, &mut prev, ret);
+                            remaining -= 1;
+                        }
+
+                        cursor = next[head];
+                        continue;
+                    }
+
+                    linearized[op / 64] &= !(1 << (op % 64));
+                }
+
+                cursor = next[idx];
+            },
+            _ => {
+                // an op completed before it could be linearized, so an earlier choice was wrong
+                if let Some(idx) = cursor {
+                    if stack.len() >= stuck_at.0 {
+                        stuck_at = (stack.len(), Some(entries[idx].op));
+                    }
+                }
+
+                let Some((idx, prev_state)) = stack.pop() else {
+                    return Err(CheckError::NotLinearizable { stuck_at: stuck_at.1 });
+                };
+
+                let op = entries[idx].op;
+                linearized[op / 64] &= !(1 << (op % 64));
+                state = prev_state;
+
+                if let Some(ret) = return_entry[op] {
+                    relink(&mut next, &mut prev, ret);
+                    remaining += 1;
+                }
+                relink(&mut next, &mut prev, idx);
+
+                cursor = next[idx];
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(value: &str) -> DbState {
+        DbState::parse(&format!(
+            "<http://example.org/s> <http://example.org/p> \"{value}\" .\n"
+        ))
+        .unwrap()
+    }
+
+    fn event(kind: EventKind, value: &str, invoked: u64, completed: Option<u64>) -> Event {
+        Event {
+            process: "update worker 1".into(),
+            kind,
+            source: StateSource::GspGraph("http://example.org/g".to_owned()),
+            value: state(value),
+            invoked: Duration::from_millis(invoked),
+            completed: completed.map(Duration::from_millis),
+        }
+    }
+
+    fn write(value: &str, invoked: u64, completed: Option<u64>) -> Event {
+        event(EventKind::Write, value, invoked, completed)
+    }
+
+    fn read(value: &str, invoked: u64, completed: u64) -> Event {
+        event(EventKind::Read, value, invoked, Some(completed))
+    }
+
+    fn check(events: &[Event]) -> Result<(), CheckError> {
+        let ops: Vec<Op> = events
+            .iter()
+            .enumerate()
+            .map(|(idx, event)| Op { event: Some(idx), invoked: event.invoked, completed: event.completed })
+            .collect();
+
+        check_key(events, &ops, MAX_SEARCH_STATES)
+    }
+
+    #[test]
+    fn concurrent_history_is_linearizable() {
+        let events = [
+            read("0", 0, 1),
+            write("1", 2, Some(10)),
+            // concurrent to the write, may observe either state
+            read("0", 3, 4),
+            read("1", 5, 6),
+            write("2", 8, Some(20)),
+            read("1", 12, 14),
+            read("2", 21, 22),
+        ];
+
+        assert_eq!(check(&events), Ok(()));
+    }
+
+    #[test]
+    fn stale_read_after_acknowledged_write() {
+        let events = [
+            write("1", 0, Some(5)),
+            read("1", 6, 7),
+            write("2", 8, Some(10)),
+            read("1", 11, 12),
+        ];
+
+        assert_eq!(check(&events), Err(CheckError::NotLinearizable { stuck_at: Some(3) }));
+    }
+
+    #[test]
+    fn unknown_outcome_may_take_effect() {
+        let events = [
+            write("1", 0, Some(5)),
+            write("2", 6, None),
+            read("2", 10, 11),
+            read("2", 12, 13),
+        ];
+
+        assert_eq!(check(&events), Ok(()));
+    }
+
+    #[test]
+    fn unknown_outcome_may_not_take_effect() {
+        let events = [
+            write("1", 0, Some(5)),
+            write("2", 6, None),
+            read("1", 10, 11),
+            read("1", 12, 13),
+        ];
+
+        assert_eq!(check(&events), Ok(()));
+    }
+
+    #[test]
+    fn unknown_outcome_cannot_be_undone() {
+        let events = [
+            write("1", 0, Some(5)),
+            write("2", 6, None),
+            read("2", 10, 11),
+            read("1", 12, 13),
+        ];
+
+        assert!(matches!(check(&events), Err(CheckError::NotLinearizable { .. })));
+    }
+
+    #[test]
+    fn invoked_at_completion_of_another_op_is_concurrent() {
+        let concurrent = [write("1", 0, Some(5)), write("2", 10, Some(20)), read("1", 20, 25)];
+        assert_eq!(check(&concurrent), Ok(()));
+
+        let after = [write("1", 0, Some(5)), write("2", 10, Some(20)), read("1", 21, 25)];
+        assert!(matches!(check(&after), Err(CheckError::NotLinearizable { .. })));
+    }
+
+    #[test]
+    fn search_budget() {
+        let events: Vec<Event> = (0..10)
+            .map(|idx| write(&idx.to_string(), 0, Some(100)))
+            .chain([read("0", 101, 102)])
+            .collect();
+        let ops: Vec<Op> = events
+            .iter()
+            .enumerate()
+            .map(|(idx, event)| Op { event: Some(idx), invoked: event.invoked, completed: event.completed })
+            .collect();
+
+        assert_eq!(check_key(&events, &ops, 100), Err(CheckError::Inconclusive));
+        assert_eq!(check_key(&events, &ops, MAX_SEARCH_STATES), Ok(()));
+    }
+
+    #[test]
+    fn resources_of_queries() {
+        let resources = resources(&StateSource::Query(
+            "CONSTRUCT { ?s ?p ?o } WHERE { { <http://example.org/a> ?p ?o } UNION \
+             { GRAPH <http://example.org/g> { ?s ?p ?o FILTER EXISTS { <http://example.org/b> ?p ?o } } } }"
+                .to_owned(),
+        ));
+
+        assert_eq!(
+            resources,
+            [
+                Resource { graph: None, subject: Some("http://example.org/a".to_owned()) },
+                Resource { graph: Some("http://example.org/g".to_owned()), subject: None },
+                Resource {
+                    graph: Some("http://example.org/g".to_owned()),
+                    subject: Some("http://example.org/b".to_owned())
+                },
+            ]
+        );
+    }
+
+    /// Worker 2 changes the data worker 1 validates, through a different validation source
+    fn shared_history(worker_2_subject: &str) -> History {
+        let history = History::new();
+        let source_1 = StateSource::Query(
+            "CONSTRUCT { <http://example.org/s> ?p ?o } WHERE { GRAPH <http://example.org/g> { <http://example.org/s> ?p ?o } }"
+                .to_owned(),
+        );
+        let source_2 = StateSource::Query(format!(
+            "CONSTRUCT {{ <{worker_2_subject}> ?p ?o }} WHERE {{ GRAPH <http://example.org/g> {{ <{worker_2_subject}> ?p ?o . }} }}"
+        ));
+        let ms = Duration::from_millis;
+
+        let worker_1 = history.recorder("update worker 1");
+        worker_1.record(EventKind::Write, &source_1, &state("1"), ms(0), Some(ms(5)));
+        history
+            .recorder("update worker 2")
+            .record(EventKind::Write, &source_2, &state("2"), ms(10), Some(ms(15)));
+        worker_1.record(EventKind::Read, &source_1, &state("2"), ms(20), Some(ms(25)));
+
+        history
+    }
+
+    #[test]
+    fn writes_of_other_workers_to_overlapping_sources() {
+        let report = shared_history("http://example.org/s").check();
+        assert_eq!(report.n_keys, 2);
+        assert!(report.violations.is_empty());
+
+        let report = shared_history("http://example.org/other").check();
+        assert_eq!(report.violations.len(), 1);
+    }
+}
@@ -0,0 +1,330 @@
+use crate::{
+    db_state::StateForm,
+    durability::RestartGate,
+    error::{InvariantVerboseInfo, WorkerError},
+    operation::StateSource,
+    update_worker::StateReader,
+    Query, WorkerBehaviour,
+};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io,
+    ops::ControlFlow,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Name of the file in the workload directory that defines the invariants
+pub const INVARIANTS_FILE: &str = "invariants.json";
+
+/// A property of data that is shared by several update workers, e.g. the number of triples in a shared graph.
+///
+/// Its `query` must return a single integer, which must equal `initial` plus the deltas (see
+/// [`UpdateOperation::invariant_deltas`](crate::operation::UpdateOperation)) of all operations the server applied.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Invariant {
+    pub name: String,
+    pub query: Query,
+    #[serde(default)]
+    pub initial: i64,
+    /// If set, no operation changes the value, e.g. the total balance of accounts that only transfer money
+    /// between each other. Readers then continuously check that the value always equals `initial`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub conserved: bool,
+}
+
+/// Reads the invariants of a workload, returns an empty list if it does not define any
+pub fn load_invariants(workload_dir: &Path) -> anyhow::Result<Vec<Invariant>> {
+    let path = workload_dir.join(INVARIANTS_FILE);
+
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(format!("Unable to open {}", path.display())),
+    };
+
+    let invariants: Vec<Invariant> =
+        serde_json::from_reader(file).context(format!("Unable to deserialize {}", path.display()))?;
+
+    for invariant in &invariants {
+        anyhow::ensure!(
+            matches!(StateForm::of_query(&invariant.query), Ok(StateForm::Solutions { .. })),
+            "The query of invariant {} must be a SELECT query",
+            invariant.name
+        );
+    }
+
+    Ok(invariants)
+}
+
+#[derive(Default)]
+struct InvariantProgress {
+    /// The deltas of all operations that were issued, including the ones still in flight
+    issued: DeltaSums,
+    /// The deltas of all acknowledged operations
+    acked: DeltaSums,
+}
+
+/// Positive and negative deltas are summed up separately, as an operation in flight may or may not have been applied
+#[derive(Default, Clone, Copy)]
+struct DeltaSums {
+    increase: i64,
+    /// Sum of the negative deltas, i.e. at most 0
+    decrease: i64,
+}
+
+/// Keeps track of the operations the update workers issued and the server acknowledged,
+/// which bound the values the invariants may have.
+#[derive(Clone)]
+pub struct InvariantTracker {
+    invariants: Arc<Vec<Arc<Invariant>>>,
+    progress: Arc<Mutex<BTreeMap<String, InvariantProgress>>>,
+}
+
+impl InvariantTracker {
+    pub fn new(invariants: Vec<Invariant>) -> Self {
+        let progress = invariants
+            .iter()
+            .map(|invariant| (invariant.name.clone(), InvariantProgress::default()))
+            .collect();
+
+        Self {
+            invariants: Arc::new(invariants.into_iter().map(Arc::new).collect()),
+            progress: Arc::new(Mutex::new(progress)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.invariants.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Invariant> {
+        self.invariants
+            .iter()
+            .find(|invariant| invariant.name == name)
+            .map(AsRef::as_ref)
+    }
+
+    /// The invariants whose value never changes
+    pub fn conserved(&self) -> Vec<Arc<Invariant>> {
+        self.invariants
+            .iter()
+            .filter(|invariant| invariant.conserved)
+            .cloned()
+            .collect()
+    }
+
+    /// Must be called before the first request of an operation is sent
+    pub fn issued(&self, deltas: &BTreeMap<String, i64>) {
+        self.add_deltas(deltas, |progress| &mut progress.issued);
+    }
+
+    /// Must be called after the server acknowledged the last request of an operation
+    pub fn acknowledged(&self, deltas: &BTreeMap<String, i64>) {
+        self.add_deltas(deltas, |progress| &mut progress.acked);
+    }
+
+    fn add_deltas(&self, deltas: &BTreeMap<String, i64>, sums: impl Fn(&mut InvariantProgress) -> &mut DeltaSums) {
+        let mut progress = self.progress.lock().unwrap();
+
+        for (name, delta) in deltas {
+            let sums = sums(progress.get_mut(name).unwrap());
+
+            if *delta >= 0 {
+                sums.increase = sums.increase.saturating_add(*delta);
+            } else {
+                sums.decrease = sums.decrease.saturating_add(*delta);
+            }
+        }
+    }
+}
+
+/// Checks the invariants periodically while the update workers are running, and once more after they finished
+pub struct InvariantChecker {
+    tracker: InvariantTracker,
+    state_reader: StateReader,
+    behav: WorkerBehaviour,
+    interval: Option<Duration>,
+    restart_gate: Option<RestartGate>,
+    verbose: bool,
+    n_checks: usize,
+}
+
+impl InvariantChecker {
+    /// Without an `interval` the invariants are only checked after the update workers finished.
+    /// If the server cannot be reached, the checker waits at the `restart_gate` before it queries again.
+    pub fn new(
+        tracker: InvariantTracker,
+        state_reader: StateReader,
+        behav: WorkerBehaviour,
+        interval: Option<Duration>,
+        restart_gate: Option<RestartGate>,
+        verbose: bool,
+    ) -> Self {
+        Self { tracker, state_reader, behav, interval, restart_gate, verbose, n_checks: 0 }
+    }
+
+    /// Number of times the invariants were checked so far
+    pub fn n_checks(&self) -> usize {
+        self.n_checks
+    }
+
+    /// Checks that the value of every invariant lies between the lowest and the highest value implied by
+    /// the acknowledged operations plus any subset of the operations in flight. The lowest value includes
+    /// the decreases of all issued operations and the highest value their increases.
+    /// Once no operation is in flight anymore, both are equal.
+    pub async fn check(&mut self) -> Result<(), WorkerError> {
+        for invariant in self.tracker.invariants.iter() {
+            let source = StateSource::Query(invariant.query.clone());
+            let form = StateForm::Solutions { ordered: false };
+
+            // operations may be issued and acknowledged while the query runs, so the acknowledged deltas have to be
+            // taken before it and the issued deltas after it
+            let acked = self.tracker.progress.lock().unwrap()[&invariant.name].acked;
+
+            let actual = loop {
+                match self.state_reader.fetch(&source, form, self.behav).await {
+                    Ok(ControlFlow::Continue(())) => {
+                        if let Some(gate) = &self.restart_gate {
+                            gate.wait().await;
+                        }
+                    },
+                    Ok(ControlFlow::Break(state)) => break state,
+                    Err(err) => return Err(WorkerError::ReadFailed { query: invariant.query.clone(), err }),
+                }
+            };
+
+            let issued = self.tracker.progress.lock().unwrap()[&invariant.name].issued;
+            let min = invariant
+                .initial
+                .saturating_add(acked.increase)
+                .saturating_add(issued.decrease);
+            let max = invariant
+                .initial
+                .saturating_add(issued.increase)
+                .saturating_add(acked.decrease);
+
+            match actual.as_integer() {
+                Some(value) if (min..=max).contains(&value) => (),
+                value => {
+                    return Err(WorkerError::InvariantViolation {
+                        invariant: invariant.name.clone(),
+                        min,
+                        max,
+                        actual: value,
+                        verbose_info: if self.verbose {
+                            Some(InvariantVerboseInfo { query: invariant.query.clone(), actual: actual.to_string() })
+                        } else {
+                            None
+                        },
+                    })
+                },
+            }
+        }
+
+        self.n_checks += 1;
+        Ok(())
+    }
+
+    /// Periodically checks the invariants until `stop` is received
+    pub async fn execute(&mut self, mut stop: tokio::sync::broadcast::Receiver<()>) -> Result<(), WorkerError> {
+        let Some(interval) = self.interval else {
+            return Ok(());
+        };
+
+        let worker = async {
+            loop {
+                tokio::time::sleep(interval).await;
+                self.check().await?;
+            }
+        };
+
+        tokio::select! {
+            res = worker => res,
+            _ = stop.recv() => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, StubResponse};
+    use reqwest::Client;
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    const INVARIANT: &str = "size";
+
+    /// A checker of a single invariant with the initial value 10, whose query returns the value of `actual`
+    async fn checker(actual: Arc<AtomicI64>) -> InvariantChecker {
+        let query_endpoint = test_server::serve(move |_| StubResponse {
+            status: 200,
+            content_type: "application/sparql-results+json",
+            body: format!(
+                r#"{{ "head": {{ "vars": ["n"] }}, "results": {{ "bindings": [{{ "n": {{ "type": "literal",
+                   "datatype": "http://www.w3.org/2001/XMLSchema#integer", "value": "{}" }} }}] }} }}"#,
+                actual.load(Ordering::SeqCst)
+            ),
+        })
+        .await;
+
+        let tracker = InvariantTracker::new(vec![Invariant {
+            name: INVARIANT.to_owned(),
+            query: "SELECT (COUNT(*) AS ?n) WHERE { ?s ?p ?o }".to_owned(),
+            initial: 10,
+            conserved: false,
+        }]);
+
+        InvariantChecker::new(
+            tracker,
+            StateReader::new(Client::new(), query_endpoint, None, None),
+            WorkerBehaviour::ReportConnectionError,
+            None,
+            None,
+            false,
+        )
+    }
+
+    /// Checks every value from `min - 1` to `max + 1`, of which only those from `min` to `max` must be accepted
+    async fn assert_bounds(checker: &mut InvariantChecker, actual: &AtomicI64, min: i64, max: i64) {
+        for value in min - 1..=max + 1 {
+            actual.store(value, Ordering::SeqCst);
+
+            match checker.check().await {
+                Ok(()) => assert!((min..=max).contains(&value), "{value} was accepted"),
+                Err(WorkerError::InvariantViolation { invariant, min: err_min, max: err_max, actual, .. }) => {
+                    assert!(!(min..=max).contains(&value), "{value} was rejected");
+                    assert_eq!((invariant.as_str(), err_min, err_max, actual), (INVARIANT, min, max, Some(value)));
+                },
+                Err(e) => panic!("Unexpected error {e}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn operations_in_flight_widen_the_bounds() {
+        let actual = Arc::new(AtomicI64::new(0));
+        let mut checker = checker(actual.clone()).await;
+        let tracker = checker.tracker.clone();
+
+        let increase = BTreeMap::from([(INVARIANT.to_owned(), 5)]);
+        let decrease = BTreeMap::from([(INVARIANT.to_owned(), -3)]);
+
+        assert_bounds(&mut checker, &actual, 10, 10).await;
+
+        // each operation in flight may or may not have been applied
+        tracker.issued(&increase);
+        tracker.issued(&decrease);
+        assert_bounds(&mut checker, &actual, 7, 15).await;
+
+        tracker.acknowledged(&increase);
+        assert_bounds(&mut checker, &actual, 12, 15).await;
+
+        tracker.acknowledged(&decrease);
+        assert_bounds(&mut checker, &actual, 12, 12).await;
+    }
+}
@@ -0,0 +1,193 @@
+use crate::{
+    error::WorkerError,
+    report::{Report, UpdateWorkerReport},
+};
+use anyhow::Context;
+use std::{fmt::Write, path::Path};
+
+enum Outcome<'a> {
+    Passed,
+    /// The error, and its message including the verbose info if it was removed from the error
+    Failed(&'a WorkerError, Option<&'a str>),
+    Skipped,
+}
+
+struct TestCase<'a> {
+    name: String,
+    time_s: f64,
+    outcome: Outcome<'a>,
+}
+
+struct TestSuite<'a> {
+    name: String,
+    cases: Vec<TestCase<'a>>,
+}
+
+/// Writes the result of a `verify` run as JUnit XML.
+///
+/// Every update worker becomes a test suite (named after its `worker_N` directory)
+/// with one test case per operation. Violations of the expected behaviour (e.g. an unexpected state)
+/// are reported as failures, all other errors (e.g. a failed request) as errors.
+/// Operations that were not executed because a previous operation failed are skipped.
+pub fn write_junit(report: &Report, path: &Path) -> anyhow::Result<()> {
+    let mut suites: Vec<TestSuite> = report.update_workers.iter().map(update_worker_suite).collect();
+    suites.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if !report.readers.is_empty() {
+        suites.push(TestSuite {
+            name: "random_read_workers".to_owned(),
+            cases: report
+                .readers
+                .iter()
+                .map(|reader| TestCase {
+                    name: format!("random_read_worker_{}", reader.worker_id),
+                    time_s: 0.0,
+                    outcome: reader
+                        .error
+                        .as_ref()
+                        .map_or(Outcome::Passed, |e| Outcome::Failed(e, reader.error_details.as_deref())),
+                })
+                .collect(),
+        });
+    }
+
+    if let Some(kill_worker) = &report.kill_worker {
+        suites.push(TestSuite {
+            name: "kill_worker".to_owned(),
+            cases: vec![TestCase {
+                name: "kill_restart_cycles".to_owned(),
+                time_s: kill_worker
+                    .cycles
+                    .iter()
+                    .map(|c| c.kill_duration_s + c.restart_duration_s + c.check_duration_s)
+                    .sum(),
+                outcome: kill_worker.error.as_ref().map_or(Outcome::Passed, |e| {
+                    Outcome::Failed(e, kill_worker.error_details.as_deref())
+                }),
+            }],
+        });
+    }
+
+    std::fs::write(path, render(&suites)).context("Unable to write JUnit file")
+}
+
+fn update_worker_suite(worker: &UpdateWorkerReport) -> TestSuite<'_> {
+    let n_completed = worker.operation_durations_s.len();
+    let failed_op = worker.error.as_ref().map(|e| e.update_id().unwrap_or(n_completed));
+
+    let cases = (0..worker.num_operations)
+        .map(|op| {
+            let outcome = match (&worker.error, failed_op) {
+                (Some(e), Some(failed_op)) if op == failed_op => Outcome::Failed(e, worker.error_details.as_deref()),
+                (Some(_), Some(failed_op)) if op > failed_op => Outcome::Skipped,
+                _ => Outcome::Passed,
+            };
+
+            TestCase {
+                name: format!("op_{op}.json"),
+                time_s: worker.operation_durations_s.get(op).copied().unwrap_or(0.0),
+                outcome,
+            }
+        })
+        .collect();
+
+    TestSuite { name: worker.workload_dir.clone(), cases }
+}
+
+fn render(suites: &[TestSuite]) -> String {
+    let count = |suite: &TestSuite, f: fn(&Outcome) -> bool| suite.cases.iter().filter(|c| f(&c.outcome)).count();
+    let is_failure = |o: &Outcome| matches!(o, Outcome::Failed(e, _) if e.is_violation());
+    let is_error = |o: &Outcome| matches!(o, Outcome::Failed(e, _) if !e.is_violation());
+    let is_skipped = |o: &Outcome| matches!(o, Outcome::Skipped);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+
+    let total_tests: usize = suites.iter().map(|s| s.cases.len()).sum();
+    let total_failures: usize = suites.iter().map(|s| count(s, is_failure)).sum();
+    let total_errors: usize = suites.iter().map(|s| count(s, is_error)).sum();
+
+    writeln!(
+        xml,
+        "<testsuites name=\"sparql-transactional-test\" tests=\"{total_tests}\" failures=\"{total_failures}\" errors=\"{total_errors}\">"
+    )
+    .unwrap();
+
+    for suite in suites {
+        let time_s: f64 = suite.cases.iter().map(|c| c.time_s).sum();
+
+        writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{time_s:.6}\">",
+            escape(&suite.name),
+            suite.cases.len(),
+            count(suite, is_failure),
+            count(suite, is_error),
+            count(suite, is_skipped),
+        )
+        .unwrap();
+
+        for case in &suite.cases {
+            let attrs = format!(
+                "name=\"{}\" classname=\"{}\" time=\"{:.6}\"",
+                escape(&case.name),
+                escape(&suite.name),
+                case.time_s
+            );
+
+            match case.outcome {
+                Outcome::Passed => writeln!(xml, "    <testcase {attrs}/>").unwrap(),
+                Outcome::Skipped => writeln!(
+                    xml,
+                    "    <testcase {attrs}>\n      <skipped message=\"A previous operation failed\"/>\n    </testcase>"
+                )
+                .unwrap(),
+                Outcome::Failed(e, details) => {
+                    let tag = if e.is_violation() { "failure" } else { "error" };
+                    let text = details.map_or_else(|| e.to_string(), ToOwned::to_owned);
+                    let message = text.lines().next().unwrap_or_default();
+
+                    writeln!(
+                        xml,
+                        "    <testcase {attrs}>\n      <{tag} message=\"{}\" type=\"{}\">{}</{tag}>\n    </testcase>",
+                        escape(message),
+                        e.kind(),
+                        escape(&text)
+                    )
+                    .unwrap();
+                },
+            }
+        }
+
+        writeln!(xml, "  </testsuite>").unwrap();
+    }
+
+    writeln!(xml, "</testsuites>").unwrap();
+    xml
+}
+
+/// Escapes text for use in XML attributes and text nodes.
+/// Also removes the terminal color codes of diffs and other characters that are not allowed in XML.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                // skip ANSI escape sequence, e.g. `\x1b[31m`
+                if chars.next_if_eq(&'[').is_some() {
+                    while chars.next().is_some_and(|c| !c.is_ascii_alphabetic()) {}
+                }
+            },
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' | '\t' | '\r' => escaped.push(c),
+            c if c.is_control() => (),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
@@ -1,57 +1,509 @@
-use crate::error::WorkerError;
+use crate::{
+    db_state::{DbState, StateForm},
+    durability::{AckedOperation, DurabilityCheck, DurabilityTracker, KillTarget},
+    error::{InvalidStateVerboseInfo, WorkerError},
+    operation::StateSource,
+    power_cut::PowerCut,
+    readiness::ReadinessProbe,
+    server::ServerControl,
+    update_worker::StateReader,
+    WorkerBehaviour,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::{
-    ffi::{OsStr, OsString},
-    io,
-    time::Duration,
+    collections::BTreeSet,
+    fmt::{Display, Formatter},
+    ops::ControlFlow,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use tokio::process::Command;
+
+/// When the kill worker kills the server, counted from the end of the previous restart (or the test start)
+#[derive(Debug, Clone, Copy, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KillSchedule {
+    /// After a fixed delay
+    Fixed,
+    /// After a delay drawn uniformly from the bounds
+    Uniform,
+    /// After an exponentially distributed delay (clamped to the bounds), i.e. the kills form a Poisson process
+    Exponential,
+    /// As soon as a number of updates were acknowledged
+    AfterAcks,
+    /// As soon as an update is in flight, after waiting for a delay drawn like for `uniform`
+    InFlight,
+    /// As soon as an update is acknowledged, after waiting for a delay drawn like for `uniform`.
+    /// If target operations are given, only their acknowledgements trigger kills.
+    AfterAck,
+}
+
+/// An operation of the workload, written like its file `worker_N/op_M.json` (without the extension)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OperationRef {
+    /// The N of `worker_N`, i.e. the update worker id minus one
+    pub worker: usize,
+    pub op: usize,
+}
+
+impl OperationRef {
+    fn update_worker_id(&self) -> usize {
+        self.worker + 1
+    }
+}
+
+impl FromStr for OperationRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let (worker, op) = s.trim_end_matches(".json").split_once('/')?;
+            Some(Self {
+                worker: worker.strip_prefix("worker_")?.parse().ok()?,
+                op: op.strip_prefix("op_")?.parse().ok()?,
+            })
+        };
+
+        parse().ok_or_else(|| anyhow::anyhow!("Expected an operation like worker_0/op_3, got {s}"))
+    }
+}
+
+impl Display for OperationRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "worker_{}/op_{}", self.worker, self.op)
+    }
+}
+
+/// Decides when the server is killed next, according to a [`KillSchedule`]
+pub struct KillScheduler {
+    schedule: KillSchedule,
+    /// The fixed delay, and the mean of the random delays unless `max_delay` is given
+    delay: Duration,
+    min_delay: Duration,
+    max_delay: Option<Duration>,
+    n_acks: usize,
+    /// The operations whose acknowledgements trigger kills with `after-ack`, any operation if not given
+    targets: Option<BTreeSet<OperationRef>>,
+    seed: u64,
+    rng: StdRng,
+}
+
+impl KillScheduler {
+    pub fn new(
+        schedule: KillSchedule,
+        delay: Duration,
+        min_delay: Duration,
+        max_delay: Option<Duration>,
+        n_acks: usize,
+        targets: Option<BTreeSet<OperationRef>>,
+        seed: u64,
+    ) -> anyhow::Result<Self> {
+        if let Some(max_delay) = max_delay {
+            anyhow::ensure!(
+                min_delay <= max_delay,
+                "The minimum kill delay must not exceed the maximum kill delay"
+            );
+        }
+
+        anyhow::ensure!(
+            n_acks >= 1,
+            "The number of acknowledgements before a kill must be at least 1"
+        );
+
+        anyhow::ensure!(
+            targets.is_none() || matches!(schedule, KillSchedule::AfterAck),
+            "Target operations can only be given for the after-ack kill schedule"
+        );
+
+        Ok(Self {
+            schedule,
+            delay,
+            min_delay,
+            max_delay,
+            n_acks,
+            targets,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        })
+    }
+
+    /// Fails if a target operation is not part of the workload, given as the number of operations
+    /// of every update worker
+    pub fn check_targets(&self, num_operations: &[usize]) -> anyhow::Result<()> {
+        for target in self.targets.iter().flatten() {
+            anyhow::ensure!(
+                num_operations.get(target.worker).is_some_and(|n| target.op < *n),
+                "The target operation {target} is not part of the workload"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Lets the acknowledgements of the target operations trigger kills, must be called before the update workers
+    /// are started so that no acknowledgement is missed
+    fn register_targets(&self, durability: &DurabilityTracker) {
+        if let Some(targets) = &self.targets {
+            durability.set_kill_targets(targets.iter().map(|op| (op.update_worker_id(), op.op)));
+        }
+    }
+
+    /// The seed of the random delays, which replays the same schedule
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Without an explicit maximum, uniform delays are drawn from the minimum up to twice the fixed delay,
+    /// while exponential delays are unbounded
+    fn random_delay(&mut self) -> Duration {
+        match self.schedule {
+            KillSchedule::Exponential => {
+                let u: f64 = self.rng.random();
+                Duration::from_secs_f64(-self.delay.as_secs_f64() * (1.0 - u).ln())
+                    .clamp(self.min_delay, self.max_delay.unwrap_or(Duration::MAX))
+            },
+            _ => {
+                let max_delay = self.max_delay.unwrap_or(2 * self.delay).max(self.min_delay);
+                self.rng.random_range(self.min_delay..=max_delay)
+            },
+        }
+    }
+
+    /// Waits until the server is to be killed next.
+    /// Returns the acknowledged operation that triggered the kill with `after-ack`.
+    async fn wait(&mut self, durability: &DurabilityTracker) -> Option<KillTarget> {
+        match self.schedule {
+            KillSchedule::Fixed => tokio::time::sleep(self.delay).await,
+            KillSchedule::Uniform | KillSchedule::Exponential => tokio::time::sleep(self.random_delay()).await,
+            KillSchedule::AfterAcks => durability.wait_for_acks(durability.n_acked() + self.n_acks).await,
+            KillSchedule::InFlight => {
+                tokio::time::sleep(self.random_delay()).await;
+                durability.wait_for_in_flight().await;
+            },
+            KillSchedule::AfterAck => {
+                if self.targets.is_none() {
+                    tokio::time::sleep(self.random_delay()).await;
+                    return durability.arm_kill_trigger().await.ok();
+                }
+
+                if let Some(target) = durability.next_acked_kill_target().await {
+                    return Some(target);
+                }
+
+                // every target operation triggered its kill, no other operation may trigger one
+                tracing::info!("All target operations were acknowledged, the server is not killed anymore");
+                return std::future::pending().await;
+            },
+        }
+
+        None
+    }
+}
+
+/// Timings of a single kill-restart cycle
+#[derive(Debug, serde::Serialize)]
+pub struct KillCycle {
+    /// Time since the kill worker was started
+    pub killed_after_s: f64,
+    /// Includes dropping the unsynced data, when simulating power loss
+    pub kill_duration_s: f64,
+    pub restart_duration_s: f64,
+    /// Time from the end of the restart until the server was ready
+    pub ready_duration_s: f64,
+    pub check_duration_s: f64,
+    /// Number of acknowledged operations that were checked after the restart
+    pub n_checked: usize,
+    /// The acknowledged operation that triggered the kill, with the `after-ack` schedule
+    pub target: Option<KillTargetReport>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct KillTargetReport {
+    pub update_worker_id: usize,
+    pub update_id: usize,
+    /// Time from the acknowledgement of the operation until the kill was issued
+    pub ack_to_kill_us: f64,
+}
 
 pub struct KillWorker {
-    kill_script: OsString,
-    restart_script: OsString,
-    kill_delay: Duration,
+    server: ServerControl,
+    readiness: ReadinessProbe,
+    scheduler: KillScheduler,
+    /// Drops the unsynced data of the server after every kill, if configured
+    power_cut: Option<PowerCut>,
+    state_reader: StateReader,
+    durability: DurabilityTracker,
+    verbose: bool,
+    cycles: Vec<KillCycle>,
 }
 
 impl KillWorker {
-    pub fn new<OS: AsRef<OsStr>>(kill_script: OS, restart_script: OS, kill_delay: Duration) -> Self {
+    pub fn new(
+        server: ServerControl,
+        readiness: ReadinessProbe,
+        scheduler: KillScheduler,
+        power_cut: Option<PowerCut>,
+        state_reader: StateReader,
+        durability: DurabilityTracker,
+        verbose: bool,
+    ) -> Self {
+        scheduler.register_targets(&durability);
+
         Self {
-            kill_script: kill_script.as_ref().to_owned(),
-            restart_script: restart_script.as_ref().to_owned(),
-            kill_delay,
+            server,
+            readiness,
+            scheduler,
+            power_cut,
+            state_reader,
+            durability,
+            verbose,
+            cycles: Vec::new(),
         }
     }
 
-    async fn run_command(script: &OsStr, map_err: impl Fn(io::Error) -> WorkerError) -> Result<(), WorkerError> {
-        let mut child = Command::new("sh").arg("-c").arg(script).spawn().map_err(&map_err)?;
+    /// The seed of the kill schedule
+    pub fn seed(&self) -> u64 {
+        self.scheduler.seed()
+    }
 
-        let status = child.wait().await.map_err(&map_err)?;
-        if status.success() {
-            Ok(())
-        } else {
-            Err(map_err(io::Error::other("Exit code != 0")))
-        }
+    /// The kill-restart cycles performed so far, and the control of the server, which still has to be shut down
+    pub fn into_parts(self) -> (Vec<KillCycle>, ServerControl) {
+        (self.cycles, self.server)
     }
 
-    async fn kill(&self) -> Result<(), WorkerError> {
-        Self::run_command(&self.kill_script, WorkerError::KillFailed).await
+    /// Reads a state, retrying until the server is reachable
+    async fn read_state(&self, source: &StateSource, form: StateForm) -> Result<DbState, WorkerError> {
+        loop {
+            match self
+                .state_reader
+                .fetch(source, form, WorkerBehaviour::IgnoreConnectionError)
+                .await
+            {
+                Ok(ControlFlow::Continue(())) => continue,
+                Ok(ControlFlow::Break(state)) => break Ok(state),
+                Err(err) => break Err(WorkerError::ReadFailed { query: source.to_string(), err }),
+            }
+        }
     }
 
-    async fn restart(&self) -> Result<(), WorkerError> {
-        Self::run_command(&self.restart_script, WorkerError::RestartFailed).await
+    /// Checks that the latest acknowledged operation of every update worker, and the operation
+    /// that triggered the kill, survived the kill
+    async fn check_acknowledged_operations(
+        &self,
+        target: Option<(usize, Arc<AckedOperation>)>,
+    ) -> Result<usize, WorkerError> {
+        let mut operations = self.durability.checkable_operations();
+
+        if let Some((update_worker_id, acked)) = target {
+            if !operations.iter().any(|check| Arc::ptr_eq(&check.acked, &acked)) {
+                operations.push(DurabilityCheck { update_worker_id, acked, in_flight: None });
+            }
+        }
+
+        tracing::info!(
+            "Server restarted, checking durability of {} operations ({} acknowledged in total)",
+            operations.len(),
+            self.durability.n_acked()
+        );
+
+        let n_checked = operations.len();
+
+        for DurabilityCheck { update_worker_id, acked: op, in_flight } in operations {
+            let actual = self.read_state(&op.source, op.expected.form()).await?;
+
+            if actual == op.expected {
+                continue;
+            }
+
+            // the update in flight at the kill may have overwritten the state after the acknowledged operation
+            if let Some(in_flight) = &in_flight {
+                let mut applied = false;
+
+                for (source, expected) in &in_flight.post_states {
+                    let state = if *source == op.source {
+                        actual.clone()
+                    } else {
+                        self.read_state(source, expected.form()).await?
+                    };
+
+                    if state == *expected {
+                        applied = true;
+                        break;
+                    }
+                }
+
+                if applied {
+                    tracing::info!(
+                        "Update {} of update worker {update_worker_id}, which was in flight, was applied before the kill",
+                        in_flight.update_id
+                    );
+                    continue;
+                }
+            }
+
+            return Err(WorkerError::DurabilityViolation {
+                update_worker_id,
+                update_id: op.update_id,
+                verbose_info: if self.verbose {
+                    Some(InvalidStateVerboseInfo {
+                        query: op.source.to_string(),
+                        expected: op.expected.to_string(),
+                        actual: actual.to_string(),
+                    })
+                } else {
+                    None
+                },
+            });
+        }
+
+        Ok(n_checked)
     }
 
     pub async fn execute(&mut self, mut stop: tokio::sync::broadcast::Receiver<()>) -> Result<(), WorkerError> {
+        let start = Instant::now();
+
         let worker = async {
             loop {
-                tokio::time::sleep(self.kill_delay).await;
-                self.kill().await?;
-                self.restart().await?;
+                let target = self.scheduler.wait(&self.durability).await;
+
+                let kill_start = Instant::now();
+                self.durability.kill_started();
+                self.server
+                    .kill()
+                    .await
+                    .map_err(|err| WorkerError::KillFailed { err })?;
+
+                let target = target.map(|KillTarget { update_worker_id, op, acked_at, resume }| {
+                    // the update worker that applied the operation may continue now
+                    drop(resume);
+
+                    tracing::info!(
+                        "Killed server right after update {} of update worker {update_worker_id} was acknowledged",
+                        op.update_id
+                    );
+
+                    let report = KillTargetReport {
+                        update_worker_id,
+                        update_id: op.update_id,
+                        ack_to_kill_us: kill_start.duration_since(acked_at).as_secs_f64() * 1e6,
+                    };
+
+                    (report, (update_worker_id, op))
+                });
+                let (target_report, target) = target.unzip();
+
+                // the update workers must not make progress until the acknowledged operations are checked
+                let _blocked = self.durability.block_requests().await;
+
+                if let Some(power_cut) = &self.power_cut {
+                    power_cut
+                        .cut()
+                        .await
+                        .map_err(|err| WorkerError::PowerCutFailed { err })?;
+                }
+
+                let restart_start = Instant::now();
+                self.server
+                    .restart()
+                    .await
+                    .map_err(|err| WorkerError::RestartFailed { err })?;
+
+                let ready_start = Instant::now();
+                self.readiness.wait_until_ready().await?;
+
+                let check_start = Instant::now();
+                let n_checked = self.check_acknowledged_operations(target).await?;
+                self.durability.kill_completed();
+
+                self.cycles.push(KillCycle {
+                    killed_after_s: kill_start.duration_since(start).as_secs_f64(),
+                    kill_duration_s: restart_start.duration_since(kill_start).as_secs_f64(),
+                    restart_duration_s: ready_start.duration_since(restart_start).as_secs_f64(),
+                    ready_duration_s: check_start.duration_since(ready_start).as_secs_f64(),
+                    check_duration_s: check_start.elapsed().as_secs_f64(),
+                    n_checked,
+                    target: target_report,
+                });
             }
         };
 
         tokio::select! {
             res = worker => res,
-            _ = stop.recv() => Ok(())
+            _ = stop.recv() => {
+                for (update_worker_id, update_id) in self.durability.remaining_kill_targets() {
+                    tracing::warn!(
+                        "Update {update_id} of update worker {update_worker_id} was not acknowledged, \
+                         so it did not trigger a kill"
+                    );
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(schedule: KillSchedule, max_delay: Option<Duration>, seed: u64) -> KillScheduler {
+        KillScheduler::new(
+            schedule,
+            Duration::from_millis(200),
+            Duration::from_millis(50),
+            max_delay,
+            1,
+            None,
+            seed,
+        )
+        .unwrap()
+    }
+
+    fn delays(scheduler: &mut KillScheduler) -> Vec<Duration> {
+        (0..100).map(|_| scheduler.random_delay()).collect()
+    }
+
+    #[test]
+    fn same_seed_replays_the_delays() {
+        for schedule in [KillSchedule::Uniform, KillSchedule::Exponential] {
+            let replayed = delays(&mut scheduler(schedule, None, 42));
+
+            assert_eq!(delays(&mut scheduler(schedule, None, 42)), replayed);
+            assert_ne!(delays(&mut scheduler(schedule, None, 43)), replayed);
+        }
+    }
+
+    #[test]
+    fn delays_respect_the_bounds() {
+        let min = Duration::from_millis(50);
+
+        for schedule in [KillSchedule::Uniform, KillSchedule::Exponential] {
+            let max = Duration::from_millis(300);
+            assert!(delays(&mut scheduler(schedule, Some(max), 42))
+                .iter()
+                .all(|delay| (min..=max).contains(delay)));
+        }
+
+        // without a maximum, uniform delays are bounded by twice the delay
+        assert!(delays(&mut scheduler(KillSchedule::Uniform, None, 42))
+            .iter()
+            .all(|delay| (min..=Duration::from_millis(400)).contains(delay)));
+        assert!(delays(&mut scheduler(KillSchedule::Exponential, None, 42))
+            .iter()
+            .all(|delay| *delay >= min));
+    }
+
+    #[test]
+    fn operation_refs_are_parsed() {
+        let op = OperationRef { worker: 0, op: 3 };
+
+        assert_eq!("worker_0/op_3".parse::<OperationRef>().unwrap(), op);
+        assert_eq!("worker_0/op_3.json".parse::<OperationRef>().unwrap(), op);
+        assert_eq!(op.to_string(), "worker_0/op_3");
+
+        for malformed in ["worker_0", "worker_0/3", "0/op_3", "worker_a/op_3", "worker_0/op_-1", "worker_0/op_3/op_4"] {
+            assert!(malformed.parse::<OperationRef>().is_err(), "{malformed} was parsed");
         }
     }
 }
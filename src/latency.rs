@@ -0,0 +1,77 @@
+use crate::Qps;
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use std::{
+    fmt::{Display, Formatter},
+    time::Duration,
+};
+
+/// Histogram of query latencies in microseconds
+#[derive(Clone)]
+pub struct LatencyHistogram(Histogram<u64>);
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        // latencies from 1µs up to one hour with 3 significant digits, longer ones are clamped
+        Self(Histogram::new_with_bounds(1, 3_600_000_000, 3).unwrap())
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        self.0
+            .saturating_record(latency.as_micros().try_into().unwrap_or(u64::MAX));
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        self.0
+            .add(&other.0)
+            .expect("histograms with identical bounds can always be added");
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Queries per second, if the queries were issued one after the other. 0 if no query was recorded.
+    pub fn qps(&self) -> Qps {
+        if self.is_empty() {
+            return 0.0;
+        }
+
+        1_000_000.0 / self.0.mean()
+    }
+
+    pub fn percentiles(&self) -> LatencyPercentiles {
+        let ms = |us: u64| us as f64 / 1000.0;
+
+        LatencyPercentiles {
+            count: self.0.len(),
+            p50_ms: ms(self.0.value_at_quantile(0.5)),
+            p90_ms: ms(self.0.value_at_quantile(0.9)),
+            p99_ms: ms(self.0.value_at_quantile(0.99)),
+            p999_ms: ms(self.0.value_at_quantile(0.999)),
+            max_ms: ms(self.0.max()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyPercentiles {
+    pub count: u64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub p999_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Display for LatencyPercentiles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "p50 {:.2}ms, p90 {:.2}ms, p99 {:.2}ms, p999 {:.2}ms, max {:.2}ms ({} queries)",
+            self.p50_ms, self.p90_ms, self.p99_ms, self.p999_ms, self.max_ms, self.count
+        )
+    }
+}
@@ -1,17 +1,46 @@
+mod db_state;
+mod durability;
 mod error;
+mod generate;
+mod history;
+mod invariant;
+mod junit;
 mod kill_worker;
+mod latency;
+mod operation;
+mod power_cut;
+mod query_mix;
+mod query_template;
 mod random_read_worker;
+mod readiness;
+mod report;
+mod server;
+#[cfg(test)]
+mod test_server;
 mod update_worker;
 
 use crate::{
+    durability::DurabilityTracker,
     error::WorkerError,
-    kill_worker::KillWorker,
-    random_read_worker::{FileSourceQueryGenerator, QueryGenerator},
+    generate::GenerateOpts,
+    history::History,
+    invariant::{InvariantChecker, InvariantTracker},
+    kill_worker::{KillCycle, KillSchedule, KillScheduler, KillWorker, OperationRef},
+    latency::{LatencyHistogram, LatencyPercentiles},
+    power_cut::PowerCut,
+    query_mix::QueryMixGenerator,
+    query_template::{Bindings, QueryTemplate, TemplateQueryGenerator},
+    random_read_worker::{
+        ArrivalSchedule, FileSourceQueryGenerator, QueryGenerator, QueryId, ReadMeasurements, ReadQuery, TargetRate,
+    },
+    readiness::{ReadinessCheck, ReadinessProbe},
+    report::{InvariantReport, KillWorkerReport, ReaderReport, Report, RunConfig, UpdateWorkerReport},
+    server::{KillSignal, ServerControl, ServerProcess},
 };
 use anyhow::Context;
 use clap::Parser;
-use random_read_worker::{RandomLimitSelectStartQueryGenerator, RandomReadWorker};
-use reqwest::Url;
+use random_read_worker::{RandomLimitSelectStartQueryGenerator, RandomReadWorker, ReaderConfig};
+use reqwest::{Client, Url};
 use std::{
     collections::BTreeMap,
     ffi::OsString,
@@ -21,7 +50,7 @@ use std::{
     time::Duration,
 };
 use tokio::{select, sync::Barrier};
-use update_worker::UpdateWorker;
+use update_worker::{StateReader, UpdateWorker, UpdateWorkerConfig};
 
 type Query = String;
 type Qps = f64;
@@ -30,22 +59,38 @@ type AvgQps = f64;
 #[derive(serde::Serialize)]
 struct QPSMeasurement {
     reader: usize,
-    query_id: usize,
+    query_id: QueryId,
     qps: f64,
+    p50_ms: f64,
+    p90_ms: f64,
+    p99_ms: f64,
+    p999_ms: f64,
+    max_ms: f64,
+    timeouts: u64,
 }
 
 struct UpdateJobResult {
     worker_id: usize,
+    num_operations: usize,
+    op_durations: Vec<Duration>,
     result: Result<(), WorkerError>,
 }
 
 struct ReadJobResult {
     worker_id: usize,
-    qps_measurements: Result<BTreeMap<usize, Qps>, WorkerError>,
+    measurements: Result<ReadMeasurements, WorkerError>,
 }
 
 struct KillJobResult {
     result: Result<(), WorkerError>,
+    cycles: Vec<KillCycle>,
+    seed: u64,
+    server: ServerControl,
+}
+
+struct InvariantJobResult {
+    result: Result<(), WorkerError>,
+    invariant_checker: InvariantChecker,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -64,6 +109,38 @@ struct ReaderOpts {
     /// If not provided readers will simply run `SELECT *` with varying limits
     #[clap(short = 'q', long)]
     random_read_workers_query_file: Option<PathBuf>,
+
+    /// Optionally, a JSON file with a weighted mix of named queries that the readers should use.
+    /// Every query can also have a timeout and an expected number of results. See the README for the format.
+    #[clap(long, conflicts_with = "random_read_workers_query_file")]
+    query_mix: Option<PathBuf>,
+
+    /// Optionally, a file with a single query template that the readers should use. Every time the query is sent,
+    /// its `%name%` placeholders are replaced by a random row of --bindings-csv or --bindings-query.
+    /// See the README for the syntax.
+    #[clap(long, conflicts_with_all = ["random_read_workers_query_file", "query_mix"], requires = "bindings")]
+    query_template: Option<PathBuf>,
+
+    /// A CSV file with the bindings of --query-template, with the placeholder names in the header
+    /// and the values in SPARQL syntax
+    #[clap(long, group = "bindings", requires = "query_template")]
+    bindings_csv: Option<PathBuf>,
+
+    /// A SPARQL SELECT query whose solutions are the bindings of --query-template,
+    /// run once against the query endpoint before the test starts
+    #[clap(long, group = "bindings", requires = "query_template")]
+    bindings_query: Option<Query>,
+
+    /// If present, the readers send this many queries per second in total (split evenly between the readers),
+    /// regardless of how fast the queries are answered (open loop). Latencies are then measured from the time
+    /// a query was scheduled to be sent. If not provided each reader sends its next query as soon as the previous
+    /// one was answered.
+    #[clap(long)]
+    target_qps: Option<f64>,
+
+    /// How the queries are spaced in time when using `--target-qps`
+    #[clap(long, value_enum, default_value_t = ArrivalSchedule::Fixed, requires = "target_qps")]
+    arrival_schedule: ArrivalSchedule,
 }
 
 #[derive(Parser)]
@@ -72,29 +149,159 @@ enum VerifySubcommand {
         /// If present, the stress test will use this script to start the server on test begin.
         ///
         /// This option must be used in conjunction with --kill-script and --restart-script.
-        #[clap(long)]
-        start_script: OsString,
+        #[clap(long, required_unless_present = "server_command", requires_all = ["kill_script", "restart_script"])]
+        start_script: Option<OsString>,
 
         /// If present, the stress test will periodically
         /// kill (i.e. uncleanly shutdown) the server using this script.
         ///
         /// This option must be used in conjunction with --start-script and --restart-script.
-        #[clap(long)]
-        kill_script: OsString,
+        #[clap(long, requires_all = ["start_script", "restart_script"])]
+        kill_script: Option<OsString>,
 
         /// If present, the stress test will restart the server
         /// after a kill using this script.
         ///
         /// This option must be used in conjunction with --start-script and --kill-script.
+        #[clap(long, requires_all = ["start_script", "kill_script"])]
+        restart_script: Option<OsString>,
+
+        /// Instead of using scripts, run this shell command as a child process to start the server,
+        /// kill it by sending --kill-signal to it and restart it by running the command again.
+        ///
+        /// The command must not return before the server stops, i.e. it must not daemonize the server.
+        #[clap(long, conflicts_with_all = ["start_script", "kill_script", "restart_script"])]
+        server_command: Option<OsString>,
+
+        /// The signal the server started by --server-command is killed with
+        #[clap(long, value_enum, default_value_t = KillSignal::Kill)]
+        kill_signal: KillSignal,
+
+        /// Directory the output of the server started by --server-command is written to,
+        /// one pair of log files per server lifecycle
+        #[clap(long, default_value = "server-logs")]
+        server_log_dir: PathBuf,
+
+        /// Simulate a power loss on every kill, in addition to killing the server. The data directory of the server
+        /// must be mounted via LazyFS, with this path as its faults FIFO (`fifo_path`). After the server was killed,
+        /// LazyFS is told to drop all data the server did not fsync, before the server is restarted.
+        /// With --kill-script, the script must only return after the server process exited.
         #[clap(long)]
-        restart_script: OsString,
+        lazyfs_fifo: Option<PathBuf>,
+
+        /// The FIFO LazyFS confirms completed commands on (`fifo_path_completed`).
+        /// If given, the server is only restarted after LazyFS confirmed that the unsynced data was dropped.
+        #[clap(long, requires = "lazyfs_fifo")]
+        lazyfs_completed_fifo: Option<PathBuf>,
+
+        /// An ASK query that is polled after the server was started or restarted, until it returns true.
+        /// Only then the test starts or the update workers resume, respectively.
+        #[clap(long, default_value = "ASK {}")]
+        ready_query: Query,
+
+        /// Instead of --ready-query, poll this URL until it responds with a 2xx status
+        #[clap(long, conflicts_with = "ready_query")]
+        ready_url: Option<Url>,
+
+        /// Timeout of a single readiness probe in milliseconds
+        #[clap(long, default_value_t = 1000)]
+        ready_timeout_ms: u64,
+
+        /// The number of milliseconds between two readiness probes
+        #[clap(long, default_value_t = 100)]
+        ready_poll_interval_ms: u64,
+
+        /// The test fails if the server is not ready this many seconds after it was started or restarted
+        #[clap(long, default_value_t = 60)]
+        ready_deadline_s: u64,
 
         /// The number of seconds between server kills
         #[clap(long, default_value_t = 10)]
         kill_delay_s: u64,
+
+        /// When the server is killed. `fixed` waits --kill-delay-s after every restart, `uniform` and `exponential`
+        /// wait for a random delay with a mean of --kill-delay-s (within the bounds, if given), `after-acks` waits
+        /// until --kill-after-acks updates were acknowledged and `in-flight` kills the server while an update is
+        /// in flight, after a random delay like `uniform`. `after-ack` kills the server as soon as an update is
+        /// acknowledged, after a random delay like `uniform` or, with --kill-after-op, right after the given updates.
+        #[clap(long, value_enum, default_value_t = KillSchedule::Fixed)]
+        kill_schedule: KillSchedule,
+
+        /// Lower bound of the random delays before a kill, in milliseconds
+        #[clap(long, default_value_t = 0)]
+        min_kill_delay_ms: u64,
+
+        /// Upper bound of the random delays before a kill, in milliseconds.
+        /// Defaults to twice --kill-delay-s for `uniform` and `in-flight`, and to no bound for `exponential`.
+        #[clap(long)]
+        max_kill_delay_ms: Option<u64>,
+
+        /// The number of updates that are acknowledged between a restart and the next kill with `after-acks`
+        #[clap(long, default_value_t = 10)]
+        kill_after_acks: usize,
+
+        /// With `after-ack`, kill the server right after this operation (e.g. `worker_0/op_3`) was acknowledged,
+        /// instead of after the first acknowledgement following a random delay. Can be given multiple times.
+        #[clap(long)]
+        kill_after_op: Vec<OperationRef>,
+
+        /// Seed of the random kill delays, to replay a kill schedule.
+        /// If not given, a random seed is used, which is logged and written to the report.
+        #[clap(long)]
+        seed: Option<u64>,
     },
 }
 
+#[derive(Parser)]
+struct VerifyOpts {
+    #[clap(flatten)]
+    reader_opts: ReaderOpts,
+
+    /// Number of update workers to spawn
+    #[clap(short = 'w', long)]
+    num_update_workers: usize,
+
+    /// Path to the directory that contains the information for the updaters
+    #[clap(short = 'Q', long)]
+    update_query_dir: PathBuf,
+
+    /// URL to SPARQL endpoint for the random readers
+    query_endpoint: Url,
+
+    /// URL to SPARQL endpoint for the updaters
+    update_endpoint: Url,
+
+    /// URL to SPARQL Graph Store Protocol endpoint
+    graph_store_endpoint: Url,
+
+    /// If an error occurs, log the query string of the query that caused it.
+    /// Warning the string can potentially be very long.
+    #[clap(short = 'v', long)]
+    verbose: bool,
+
+    /// If present, write a JUnit XML file with one test suite per update worker
+    /// and one test case per operation. Its failure messages always include the expected and actual states.
+    #[clap(long)]
+    junit: Option<PathBuf>,
+
+    /// Record the history of all updates and validation reads and check at the end of the run
+    /// that it is linearizable
+    #[clap(long)]
+    check_linearizability: bool,
+
+    /// If present, write the history of all updates and validation reads as JSON lines to this file
+    #[clap(long)]
+    history: Option<PathBuf>,
+
+    /// If the workload defines invariants (in `invariants.json`), check them every given number of
+    /// milliseconds while the updates are running. They are always checked once all updates finished.
+    #[clap(long)]
+    invariant_check_interval_ms: Option<u64>,
+
+    #[clap(subcommand)]
+    sub: Option<VerifySubcommand>,
+}
+
 #[derive(Parser)]
 enum SubCommand {
     /// A read-only stress test that measures QPS
@@ -113,36 +320,10 @@ enum SubCommand {
         /// URL to SPARQL (read) endpoint to stress
         query_endpoint: Url,
     },
+    /// Generate a workload for `verify` from a seed dataset
+    Generate(GenerateOpts),
     /// A read-write workload that checks for correctness of concurrent updates and reads
-    Verify {
-        #[clap(flatten)]
-        reader_opts: ReaderOpts,
-
-        /// Number of update workers to spawn
-        #[clap(short = 'w', long)]
-        num_update_workers: usize,
-
-        /// Path to the directory that contains the information for the updaters
-        #[clap(short = 'Q', long)]
-        update_query_dir: PathBuf,
-
-        /// URL to SPARQL endpoint for the random readers
-        query_endpoint: Url,
-
-        /// URL to SPARQL endpoint for the updaters
-        update_endpoint: Url,
-
-        /// URL to SPARQL Graph Store Protocol endpoint
-        graph_store_endpoint: Url,
-
-        /// If an error occurs, log the query string of the query that caused it.
-        /// Warning the string can potentially be very long.
-        #[clap(short = 'v', long)]
-        verbose: bool,
-
-        #[clap(subcommand)]
-        sub: Option<VerifySubcommand>,
-    },
+    Verify(Box<VerifyOpts>),
 }
 
 #[derive(Parser)]
@@ -151,6 +332,11 @@ struct Command {
     #[clap(long)]
     no_color: bool,
 
+    /// If present, write a machine-readable JSON report of the `stress` or `verify` run to this file.
+    /// Expected and actual states of failed operations are only included together with `--verbose`.
+    #[clap(long, global = true)]
+    report: Option<PathBuf>,
+
     #[clap(subcommand)]
     sub: SubCommand,
 }
@@ -165,74 +351,299 @@ async fn main() {
         .init();
 
     if let Err(e) = run(opts).await {
-        tracing::error!("{e}");
+        tracing::error!("{e:#}");
         std::process::exit(1);
     }
 }
 
 async fn run(opts: Command) -> anyhow::Result<()> {
-    let (update_workers, random_read_workers, kill_worker) = match &opts.sub {
+    if let SubCommand::Generate(generate_opts) = &opts.sub {
+        return generate::generate(generate_opts);
+    }
+
+    let mut report = Report::new(run_config(&opts.sub));
+    let result = run_test(&opts, &mut report).await;
+
+    report.finish(&result);
+
+    if let Some(report_file) = &opts.report {
+        report.write(report_file)?;
+    }
+
+    if let SubCommand::Verify(verify_opts) = &opts.sub {
+        if let Some(junit_file) = &verify_opts.junit {
+            junit::write_junit(&report, junit_file)?;
+        }
+    }
+
+    result
+}
+
+fn run_config(sub: &SubCommand) -> RunConfig {
+    match sub {
+        SubCommand::Generate(_) => unreachable!("generate does not run a test"),
+        SubCommand::Stress { reader_opts, duration_s, query_endpoint, .. } => RunConfig {
+            subcommand: "stress",
+            query_endpoint: query_endpoint.to_string(),
+            update_endpoint: None,
+            graph_store_endpoint: None,
+            num_update_workers: 0,
+            update_query_dir: None,
+            num_random_read_workers: reader_opts.num_random_read_workers,
+            random_read_workers_query_file: reader_opts.random_read_workers_query_file.clone(),
+            query_mix: reader_opts.query_mix.clone(),
+            query_template: reader_opts.query_template.clone(),
+            target_qps: reader_opts.target_qps,
+            arrival_schedule: reader_opts.target_qps.map(|_| reader_opts.arrival_schedule),
+            duration_s: Some(*duration_s),
+            kill_delay_s: None,
+            kill_schedule: None,
+            kill_signal: None,
+            power_cut: false,
+            check_linearizability: false,
+        },
+        SubCommand::Verify(verify_opts) => {
+            let VerifyOpts {
+                reader_opts,
+                num_update_workers,
+                update_query_dir,
+                query_endpoint,
+                update_endpoint,
+                graph_store_endpoint,
+                check_linearizability,
+                sub,
+                ..
+            } = &**verify_opts;
+
+            RunConfig {
+                subcommand: "verify",
+                query_endpoint: query_endpoint.to_string(),
+                update_endpoint: Some(update_endpoint.to_string()),
+                graph_store_endpoint: Some(graph_store_endpoint.to_string()),
+                num_update_workers: *num_update_workers,
+                update_query_dir: Some(update_query_dir.clone()),
+                num_random_read_workers: reader_opts.num_random_read_workers,
+                random_read_workers_query_file: reader_opts.random_read_workers_query_file.clone(),
+                query_mix: reader_opts.query_mix.clone(),
+                query_template: reader_opts.query_template.clone(),
+                target_qps: reader_opts.target_qps,
+                arrival_schedule: reader_opts.target_qps.map(|_| reader_opts.arrival_schedule),
+                duration_s: None,
+                kill_delay_s: sub
+                    .as_ref()
+                    .map(|VerifySubcommand::Durability { kill_delay_s, .. }| *kill_delay_s),
+                kill_schedule: sub
+                    .as_ref()
+                    .map(|VerifySubcommand::Durability { kill_schedule, .. }| *kill_schedule),
+                kill_signal: sub.as_ref().and_then(
+                    |VerifySubcommand::Durability { server_command, kill_signal, .. }| {
+                        server_command.as_ref().map(|_| *kill_signal)
+                    },
+                ),
+                power_cut: sub
+                    .as_ref()
+                    .is_some_and(|VerifySubcommand::Durability { lazyfs_fifo, .. }| lazyfs_fifo.is_some()),
+                check_linearizability: *check_linearizability,
+            }
+        },
+    }
+}
+
+async fn run_test(opts: &Command, report: &mut Report) -> anyhow::Result<()> {
+    let verify_opts = match &opts.sub {
+        SubCommand::Verify(verify_opts) => Some(&**verify_opts),
+        _ => None,
+    };
+
+    let mut lifecycle = match verify_opts {
+        Some(VerifyOpts { sub: Some(kill_opts), query_endpoint, .. }) => Some(ServerLifecycle {
+            server: make_server_control(kill_opts),
+            readiness: make_readiness_probe(kill_opts, query_endpoint)?,
+            scheduler: make_kill_scheduler(kill_opts)?,
+            power_cut: make_power_cut(kill_opts),
+        }),
+        _ => None,
+    };
+
+    // the server has to be running before the workers are created, as loading the reader queries may query it
+    if let Some(ServerLifecycle { server, readiness, .. }) = &mut lifecycle {
+        if let Err(e) = server.start().await {
+            tracing::error!("Starting server failed. Error: {e}");
+            return Err(anyhow::anyhow!("Test failed, unable to perform lifecycle management"));
+        }
+
+        match readiness.wait_until_ready().await {
+            Ok(duration) => tracing::info!("Server ready after {:.2}s", duration.as_secs_f64()),
+            Err(e) => {
+                tracing::error!("Starting server failed. Error: {e}");
+                return Err(anyhow::anyhow!("Test failed, unable to perform lifecycle management"));
+            },
+        }
+    }
+
+    let history = match verify_opts {
+        Some(VerifyOpts { check_linearizability, history, .. }) if *check_linearizability || history.is_some() => {
+            Some(History::new())
+        },
+        _ => None,
+    };
+
+    let (update_workers, random_read_workers, kill_worker, invariant_checker) = match &opts.sub {
+        SubCommand::Generate(_) => unreachable!("generate does not run a test"),
         SubCommand::Stress { reader_opts, query_endpoint, .. } => (
             vec![],
-            make_random_readers(query_endpoint, reader_opts, WorkerBehaviour::ReportConnectionError)?,
+            make_random_readers(
+                reader_opts,
+                ReaderConfig {
+                    endpoint: query_endpoint.clone(),
+                    graph_store_endpoint: None,
+                    behav: WorkerBehaviour::ReportConnectionError,
+                    in_flight_updates: vec![],
+                    conserved: vec![],
+                    restart_gate: None,
+                    verbose: false,
+                },
+                None,
+            )
+            .await?,
+            None,
             None,
         ),
-        SubCommand::Verify {
-            reader_opts,
-            num_update_workers,
-            update_query_dir,
-            query_endpoint,
-            update_endpoint,
-            graph_store_endpoint,
-            verbose,
-            sub,
-        } => {
+        SubCommand::Verify(verify_opts) => {
+            let VerifyOpts {
+                reader_opts,
+                num_update_workers,
+                update_query_dir,
+                query_endpoint,
+                update_endpoint,
+                graph_store_endpoint,
+                verbose,
+                junit,
+                invariant_check_interval_ms,
+                sub,
+                ..
+            } = &**verify_opts;
+
+            // the verbose info is always collected for the JUnit file, but only logged and reported with `--verbose`
+            let verbose = *verbose || junit.is_some();
             let behav = if sub.is_none() {
                 WorkerBehaviour::ReportConnectionError
             } else {
                 WorkerBehaviour::IgnoreConnectionError
             };
 
-            (
-                make_update_workers(
+            let durability = sub.as_ref().map(|_| DurabilityTracker::default());
+
+            // while the server is down, the readers back off like the readiness probe
+            let restart_gate = durability.as_ref().zip(sub.as_ref()).map(
+                |(durability, VerifySubcommand::Durability { ready_poll_interval_ms, .. })| {
+                    durability.restart_gate(Duration::from_millis(*ready_poll_interval_ms))
+                },
+            );
+            let invariants = InvariantTracker::new(invariant::load_invariants(update_query_dir)?);
+
+            let update_workers = make_update_workers(
+                update_query_dir,
+                *num_update_workers,
+                UpdateWorkerConfig {
+                    query_endpoint: query_endpoint.clone(),
+                    update_endpoint: update_endpoint.clone(),
+                    graph_store_endpoint: graph_store_endpoint.clone(),
+                    verbose,
+                    behav,
+                    invariants: invariants.clone(),
+                },
+                durability.as_ref(),
+                history.as_ref(),
+            )?;
+
+            if let Some(ServerLifecycle { scheduler, .. }) = &lifecycle {
+                let num_operations: Vec<_> = update_workers.iter().map(UpdateWorker::num_operations).collect();
+                scheduler.check_targets(&num_operations)?;
+            }
+
+            let in_flight_updates: Vec<_> = update_workers
+                .iter()
+                .zip(1..)
+                .map(|(update_worker, worker_id)| (worker_id, update_worker.subscribe_in_flight()))
+                .collect();
+
+            let random_read_workers = make_random_readers(
+                reader_opts,
+                ReaderConfig {
+                    endpoint: query_endpoint.clone(),
+                    graph_store_endpoint: Some(graph_store_endpoint.clone()),
+                    behav,
+                    in_flight_updates,
+                    conserved: invariants.conserved(),
+                    restart_gate: restart_gate.clone(),
+                    verbose,
+                },
+                history.as_ref(),
+            )
+            .await?;
+
+            let kill_worker = durability.zip(lifecycle).map(|(durability, lifecycle)| {
+                make_kill_worker(
+                    lifecycle,
                     query_endpoint,
-                    update_endpoint,
                     graph_store_endpoint,
-                    *num_update_workers,
-                    update_query_dir,
-                    *verbose,
+                    durability,
+                    verbose,
+                    history.as_ref(),
+                )
+            });
+
+            let invariant_checker = (!invariants.is_empty()).then(|| {
+                InvariantChecker::new(
+                    invariants,
+                    StateReader::new(Client::new(), query_endpoint.clone(), None, None),
                     behav,
-                )?,
-                make_random_readers(query_endpoint, reader_opts, behav)?,
-                make_kill_worker(sub.as_ref()),
-            )
+                    invariant_check_interval_ms.map(Duration::from_millis),
+                    restart_gate,
+                    verbose,
+                )
+            });
+
+            (update_workers, random_read_workers, kill_worker, invariant_checker)
         },
     };
 
     let num_update_workers = update_workers.len();
     let num_random_read_workers = random_read_workers.len();
     let num_kill_workers = kill_worker.is_some() as usize;
+    let num_invariant_checkers = invariant_checker.is_some() as usize;
 
     let start_barrier = Arc::new(Barrier::new(
-        num_update_workers + num_random_read_workers + num_kill_workers + 1,
+        num_update_workers + num_random_read_workers + num_kill_workers + num_invariant_checkers + 1,
     ));
-    let (updates_finished_tx, mut updates_finished_rx) = tokio::sync::mpsc::channel(num_update_workers);
+    let (stop_notify_tx, _stop_notify_rx) = tokio::sync::broadcast::channel(1);
+    let (updates_finished_tx, mut updates_finished_rx) = tokio::sync::mpsc::channel(num_update_workers.max(1));
 
-    for (update_worker, worker_id) in update_workers.into_iter().zip(1..) {
+    for (mut update_worker, worker_id) in update_workers.into_iter().zip(1..) {
         let start_barrier = start_barrier.clone();
         let finished_tx = updates_finished_tx.clone();
+        let stop_notify = stop_notify_tx.subscribe();
 
         tokio::spawn(async move {
             start_barrier.wait().await;
             tracing::info!("Starting update worker {}", worker_id);
 
-            let result = update_worker.execute().await;
-            finished_tx.send(UpdateJobResult { worker_id, result }).await.unwrap();
+            let result = update_worker.execute(stop_notify).await;
+
+            finished_tx
+                .send(UpdateJobResult {
+                    worker_id,
+                    num_operations: update_worker.num_operations(),
+                    op_durations: update_worker.op_durations().to_vec(),
+                    result,
+                })
+                .await
+                .unwrap();
         });
     }
 
-    let (stop_notify_tx, _stop_notify_rx) = tokio::sync::broadcast::channel(1);
-    let (readers_finished_tx, mut readers_finished_rx) = tokio::sync::mpsc::channel(num_random_read_workers);
+    let (readers_finished_tx, mut readers_finished_rx) = tokio::sync::mpsc::channel(num_random_read_workers.max(1));
 
     for (mut rr_worker, worker_id) in random_read_workers.into_iter().zip(1..) {
         let start_barrier = start_barrier.clone();
@@ -243,9 +654,9 @@ async fn run(opts: Command) -> anyhow::Result<()> {
             start_barrier.wait().await;
             tracing::info!("Starting random read worker {worker_id}");
 
-            let qps_measurements = rr_worker.execute(stop_notify).await;
+            let measurements = rr_worker.execute(stop_notify).await;
             finished_tx
-                .send(ReadJobResult { worker_id, qps_measurements })
+                .send(ReadJobResult { worker_id, measurements })
                 .await
                 .unwrap();
         });
@@ -263,35 +674,38 @@ async fn run(opts: Command) -> anyhow::Result<()> {
             tracing::info!("Starting kill worker");
 
             let result = kill_worker.execute(stop_notify).await;
-            finished_tx.send(KillJobResult { result }).await.unwrap();
+            let seed = kill_worker.seed();
+            let (cycles, server) = kill_worker.into_parts();
+            finished_tx
+                .send(KillJobResult { result, cycles, seed, server })
+                .await
+                .unwrap();
         });
     }
 
+    // the invariant checker is stopped separately, as the final check has to happen before the kill worker stops
+    let (stop_invariants_tx, _stop_invariants_rx) = tokio::sync::broadcast::channel(1);
+
+    let invariant_checker = invariant_checker.map(|mut invariant_checker| {
+        let start_barrier = start_barrier.clone();
+        let stop_notify = stop_invariants_tx.subscribe();
+
+        tokio::spawn(async move {
+            start_barrier.wait().await;
+            tracing::info!("Starting invariant checker");
+
+            let result = invariant_checker.execute(stop_notify).await;
+            InvariantJobResult { result, invariant_checker }
+        })
+    });
+
     drop(updates_finished_tx);
     drop(readers_finished_tx);
     drop(kill_worker_finished_tx);
 
-    if let SubCommand::Verify { sub: Some(VerifySubcommand::Durability { start_script, .. }), .. } = &opts.sub {
-        match tokio::process::Command::new("sh")
-            .arg("-c")
-            .arg(start_script)
-            .status()
-            .await
-        {
-            Ok(status) if !status.success() => {
-                tracing::error!("Starting server failed. Error: Script returned exit status != 0");
-                return Err(anyhow::anyhow!("Test failed, unable to perform lifecycle management"));
-            },
-            Err(e) => {
-                tracing::error!("Starting server failed. Error: {e}");
-                return Err(anyhow::anyhow!("Test failed, unable to perform lifecycle management"));
-            },
-            _ => (),
-        }
-    }
-
     start_barrier.wait().await;
     let start_time = tokio::time::Instant::now();
+    report.started();
 
     if let SubCommand::Stress { duration_s, .. } = opts.sub {
         tokio::time::sleep(Duration::from_secs(duration_s)).await;
@@ -299,24 +713,43 @@ async fn run(opts: Command) -> anyhow::Result<()> {
     }
 
     let mut n_update_errors = 0;
+    let verbose = verify_opts.is_some_and(|verify_opts| verify_opts.verbose);
+
+    // if the kill worker fails, the other workers are stopped and their results are still collected for the report
+    let mut kill_job = None;
 
     loop {
         select! {
             ujobres = updates_finished_rx.recv() => {
-                if let Some(UpdateJobResult { worker_id, result }) = ujobres {
-                    if let Err(e) = result {
+                if let Some(UpdateJobResult { worker_id, num_operations, op_durations, mut result }) = ujobres {
+                    let error_details = result.as_mut().err().and_then(|e| split_error_details(e, verbose));
+
+                    if let Err(e) = &result {
                         tracing::error!("Update worker {worker_id} encountered an error: {e}");
                         n_update_errors += 1;
                     }
+
+                    report.update_workers.push(UpdateWorkerReport {
+                        worker_id,
+                        workload_dir: format!("worker_{}", worker_id - 1),
+                        finished_after_s: start_time.elapsed().as_secs_f64(),
+                        num_operations,
+                        operation_durations_s: op_durations.iter().map(Duration::as_secs_f64).collect(),
+                        error: result.err(),
+                        error_details,
+                    });
                 } else {
                     break;
                 }
             },
-            kjobres = kill_worker_finished_rx.recv() => {
-                if let Some(KillJobResult { result: Err(e) }) = kjobres {
+            Some(job) = kill_worker_finished_rx.recv() => {
+                if let KillJobResult { result: Err(e), .. } = &job {
                     tracing::error!("Kill worker encountered an error: {e}");
-                    return Err(anyhow::anyhow!("Test failed, unable to perform lifecycle management"));
+                    tracing::info!("Stopping the remaining workers");
+                    let _ = stop_notify_tx.send(());
                 }
+
+                kill_job = Some(job);
             },
         }
     }
@@ -327,69 +760,451 @@ async fn run(opts: Command) -> anyhow::Result<()> {
         end_time.duration_since(start_time).as_secs_f64()
     );
 
+    let kill_failed = matches!(kill_job, Some(KillJobResult { result: Err(_), .. }));
+
+    if let Some(invariant_checker) = invariant_checker {
+        let _ = stop_invariants_tx.send(());
+        let InvariantJobResult { mut result, mut invariant_checker } = invariant_checker.await?;
+
+        // without the kill worker, the server may not be running anymore
+        if result.is_ok() && !kill_failed {
+            result = invariant_checker.check().await;
+        }
+
+        if let (Err(e), false) = (&mut result, verbose) {
+            e.clear_verbose_info();
+        }
+
+        match &result {
+            Ok(()) => tracing::info!("Invariants held in all {} checks", invariant_checker.n_checks()),
+            Err(e) => tracing::error!("Invariant checker encountered an error: {e}"),
+        }
+
+        report.invariants = Some(InvariantReport { n_checks: invariant_checker.n_checks(), error: result.err() });
+    }
+
     let _ = stop_notify_tx.send(());
 
     let mut qps_sum: Qps = 0.0;
-
-    while let Some(ReadJobResult { worker_id, qps_measurements }) = readers_finished_rx.recv().await {
-        match qps_measurements {
-            Ok(qps_measurements) => {
-                let reader_avgqps: AvgQps = qps_measurements.values().sum::<Qps>() / qps_measurements.len() as f64;
-                tracing::info!("Random read worker {worker_id} achieved {reader_avgqps:.2} AvgQPS");
+    let mut n_unfinished = 0;
+    let mut n_reader_violations = 0;
+    let mut latencies_per_query: BTreeMap<QueryId, LatencyHistogram> = BTreeMap::new();
+    let mut timeouts_per_query: BTreeMap<QueryId, u64> = BTreeMap::new();
+    let mut n_timeouts = 0;
+    let mut latencies = LatencyHistogram::default();
+
+    while let Some(ReadJobResult { worker_id, measurements }) = readers_finished_rx.recv().await {
+        match measurements {
+            Ok(measurements) => {
+                let qps_measurements = measurements.per_query_qps();
+                let reader_avgqps = measurements.avg_qps();
+
+                if measurements.open_loop_duration.is_some() {
+                    tracing::info!("Random read worker {worker_id} answered {reader_avgqps:.2} queries per second");
+                } else {
+                    tracing::info!("Random read worker {worker_id} achieved {reader_avgqps:.2} AvgQPS");
+                }
                 qps_sum += reader_avgqps;
+                n_unfinished += measurements.all.n_unfinished;
 
                 if let SubCommand::Stress { output_per_query_qps_csv: true, .. } = &opts.sub {
                     let mut w = csv::Writer::from_writer(std::io::stdout());
 
-                    for (query_id, qps) in qps_measurements {
-                        w.serialize(QPSMeasurement { reader: worker_id, query_id, qps })?;
+                    for (query_name, query_stats) in &measurements.per_query {
+                        let LatencyPercentiles { p50_ms, p90_ms, p99_ms, p999_ms, max_ms, .. } =
+                            query_stats.latencies.percentiles();
+
+                        w.serialize(QPSMeasurement {
+                            reader: worker_id,
+                            query_id: query_name.clone(),
+                            qps: qps_measurements[query_name],
+                            p50_ms,
+                            p90_ms,
+                            p99_ms,
+                            p999_ms,
+                            max_ms,
+                            timeouts: query_stats.n_timeouts,
+                        })?;
                     }
                 }
+
+                for (query_name, query_stats) in &measurements.per_query {
+                    latencies_per_query
+                        .entry(query_name.clone())
+                        .or_default()
+                        .merge(&query_stats.latencies);
+                }
+                for (query_name, query_stats) in &measurements.per_query {
+                    *timeouts_per_query.entry(query_name.clone()).or_default() += query_stats.n_timeouts;
+                }
+                n_timeouts += measurements.all.n_timeouts;
+                latencies.merge(&measurements.all.latencies);
+
+                report.readers.push(ReaderReport {
+                    worker_id,
+                    avg_qps: Some(reader_avgqps),
+                    per_query_qps: qps_measurements,
+                    latency: (!measurements.all.latencies.is_empty()).then(|| measurements.all.latencies.percentiles()),
+                    per_query_latency: measurements
+                        .per_query
+                        .iter()
+                        .map(|(query_name, query_stats)| (query_name.clone(), query_stats.latencies.percentiles()))
+                        .collect(),
+                    per_query_timeouts: measurements
+                        .per_query
+                        .iter()
+                        .filter(|(_, query_stats)| query_stats.n_timeouts > 0)
+                        .map(|(query_name, query_stats)| (query_name.clone(), query_stats.n_timeouts))
+                        .collect(),
+                    timeouts: measurements.all.n_timeouts,
+                    unfinished_queries: measurements.all.n_unfinished,
+                    error: None,
+                    error_details: None,
+                });
             },
-            Err(e) => {
+            Err(mut e) => {
+                let error_details = split_error_details(&mut e, verbose);
                 tracing::error!("Random read worker {worker_id} encountered an error: {e}");
+
+                if e.is_violation() {
+                    n_reader_violations += 1;
+                }
+
+                report.readers.push(ReaderReport {
+                    worker_id,
+                    avg_qps: None,
+                    per_query_qps: BTreeMap::new(),
+                    latency: None,
+                    per_query_latency: BTreeMap::new(),
+                    per_query_timeouts: BTreeMap::new(),
+                    timeouts: 0,
+                    unfinished_queries: 0,
+                    error: Some(e),
+                    error_details,
+                });
             },
         }
     }
 
-    tracing::info!(
-        "The random read workers achieved {} AvgQPS",
-        qps_sum / num_random_read_workers as f64,
-    );
+    if report.config.target_qps.is_some() {
+        tracing::info!("The random read workers answered {qps_sum:.2} queries per second in total");
+    } else if num_random_read_workers > 0 {
+        tracing::info!(
+            "The random read workers achieved {} AvgQPS",
+            qps_sum / num_random_read_workers as f64,
+        );
+    }
+
+    if n_unfinished > 0 {
+        tracing::info!(
+            "{n_unfinished} queries were still unanswered when the readers were stopped, \
+             their latencies are recorded as the time until then"
+        );
+    }
+
+    for (query_name, query_latencies) in &latencies_per_query {
+        tracing::info!("Latency of query {query_name}: {}", query_latencies.percentiles());
+    }
+
+    for (query_name, n_timeouts) in timeouts_per_query.iter().filter(|(_, n_timeouts)| **n_timeouts > 0) {
+        tracing::warn!("Query {query_name} timed out {n_timeouts} times");
+    }
+
+    if n_timeouts > 0 {
+        tracing::warn!("{n_timeouts} queries timed out in total, their latencies are recorded as the time until then");
+    }
 
-    if let SubCommand::Verify { sub: Some(VerifySubcommand::Durability { kill_script, .. }), .. } = &opts.sub {
-        let _ = tokio::process::Command::new("sh").arg("-c").arg(kill_script).spawn();
+    if !latencies.is_empty() {
+        let percentiles = latencies.percentiles();
+        tracing::info!("Latency over all random read workers: {percentiles}");
+        report.latency = Some(percentiles);
     }
 
-    if n_update_errors > 0 {
+    if kill_job.is_none() {
+        kill_job = kill_worker_finished_rx.recv().await;
+    }
+
+    let mut kill_error = None;
+
+    if let Some(KillJobResult { mut result, cycles, seed, mut server }) = kill_job {
+        let error_details = result.as_mut().err().and_then(|e| split_error_details(e, verbose));
+
+        kill_error = result.as_ref().err().map(|e| {
+            if let WorkerError::DurabilityViolation { .. } = e {
+                anyhow::anyhow!("Test failed, acknowledged updates were lost")
+            } else {
+                anyhow::anyhow!("Test failed, unable to perform lifecycle management")
+            }
+        });
+
+        report.kill_worker =
+            Some(KillWorkerReport { n_kills: cycles.len(), seed, cycles, error: result.err(), error_details });
+        server.shut_down().await;
+    }
+
+    let mut n_linearizability_violations = 0;
+
+    if let (Some(history), Some(VerifyOpts { check_linearizability, history: history_file, .. })) =
+        (history, verify_opts)
+    {
+        if let Some(history_file) = history_file {
+            history.write(history_file)?;
+        }
+
+        if *check_linearizability && kill_failed {
+            tracing::warn!("Not checking the history for linearizability, as the test was stopped early");
+        } else if *check_linearizability {
+            let linearizability = tokio::task::spawn_blocking(move || history.check()).await?;
+
+            for violation in &linearizability.violations {
+                tracing::error!("{violation}");
+            }
+
+            for inconclusive in &linearizability.inconclusive {
+                tracing::warn!("{inconclusive}");
+            }
+
+            tracing::info!(
+                "Checked the history of {} events on {} validation sources, found {} linearizability violations \
+                 ({} checks were inconclusive)",
+                linearizability.n_events,
+                linearizability.n_keys,
+                linearizability.violations.len(),
+                linearizability.inconclusive.len()
+            );
+
+            n_linearizability_violations = linearizability.violations.len();
+            report.linearizability = Some(linearizability);
+        }
+    }
+
+    let invariant_error = report
+        .invariants
+        .as_ref()
+        .and_then(|invariants| invariants.error.as_ref());
+
+    if let Some(e) = kill_error {
+        Err(e)
+    } else if n_update_errors > 0 || n_reader_violations > 0 {
         Err(anyhow::anyhow!("Test failed, errors were encountered"))
+    } else if let Some(e) = invariant_error {
+        if e.is_violation() {
+            Err(anyhow::anyhow!(
+                "Test failed, an invariant of the shared data was violated"
+            ))
+        } else {
+            Err(anyhow::anyhow!("Test failed, unable to check the invariants"))
+        }
+    } else if n_linearizability_violations > 0 {
+        Err(anyhow::anyhow!("Test failed, the history is not linearizable"))
     } else {
         Ok(())
     }
 }
 
-fn make_kill_worker(kill_opts: Option<&VerifySubcommand>) -> Option<KillWorker> {
-    kill_opts.map(
-        |VerifySubcommand::Durability { kill_script, restart_script, kill_delay_s, .. }| {
-            KillWorker::new(kill_script, restart_script, Duration::from_secs(*kill_delay_s))
+/// Without `--verbose`, the verbose info of an error is only collected for the JUnit file.
+/// It is then removed from the error before the error is logged and reported, and returned as part of the message.
+fn split_error_details(e: &mut WorkerError, verbose: bool) -> Option<String> {
+    if verbose {
+        return None;
+    }
+
+    let details = e.to_string();
+    e.clear_verbose_info();
+    Some(details)
+}
+
+fn make_server_control(kill_opts: &VerifySubcommand) -> ServerControl {
+    match kill_opts {
+        VerifySubcommand::Durability { server_command: Some(command), kill_signal, server_log_dir, .. } => {
+            ServerControl::Process(ServerProcess::new(
+                command.clone(),
+                *kill_signal,
+                server_log_dir.clone(),
+            ))
+        },
+        VerifySubcommand::Durability { start_script, kill_script, restart_script, .. } => ServerControl::Scripts {
+            // clap ensures that either all scripts or a server command are given
+            start: start_script.clone().unwrap(),
+            kill: kill_script.clone().unwrap(),
+            restart: restart_script.clone().unwrap(),
         },
+    }
+}
+
+fn make_readiness_probe(kill_opts: &VerifySubcommand, query_endpoint: &Url) -> anyhow::Result<ReadinessProbe> {
+    let VerifySubcommand::Durability {
+        ready_query,
+        ready_url,
+        ready_timeout_ms,
+        ready_poll_interval_ms,
+        ready_deadline_s,
+        ..
+    } = kill_opts;
+
+    let check = match ready_url {
+        Some(url) => ReadinessCheck::Url(url.clone()),
+        None => ReadinessCheck::Ask(ready_query.clone()),
+    };
+
+    ReadinessProbe::new(
+        query_endpoint.clone(),
+        check,
+        Duration::from_millis(*ready_timeout_ms),
+        Duration::from_millis(*ready_poll_interval_ms),
+        Duration::from_secs(*ready_deadline_s),
     )
 }
 
-fn make_random_readers(
+fn make_kill_scheduler(kill_opts: &VerifySubcommand) -> anyhow::Result<KillScheduler> {
+    let VerifySubcommand::Durability {
+        kill_delay_s,
+        kill_schedule,
+        min_kill_delay_ms,
+        max_kill_delay_ms,
+        kill_after_acks,
+        kill_after_op,
+        seed,
+        ..
+    } = kill_opts;
+
+    let seed = seed.unwrap_or_else(rand::random);
+    tracing::info!("Using kill schedule {kill_schedule:?} with seed {seed}");
+
+    KillScheduler::new(
+        *kill_schedule,
+        Duration::from_secs(*kill_delay_s),
+        Duration::from_millis(*min_kill_delay_ms),
+        max_kill_delay_ms.map(Duration::from_millis),
+        *kill_after_acks,
+        (!kill_after_op.is_empty()).then(|| kill_after_op.iter().copied().collect()),
+        seed,
+    )
+}
+
+fn make_power_cut(kill_opts: &VerifySubcommand) -> Option<PowerCut> {
+    let VerifySubcommand::Durability { lazyfs_fifo, lazyfs_completed_fifo, .. } = kill_opts;
+
+    lazyfs_fifo
+        .clone()
+        .map(|fifo| PowerCut::new(fifo, lazyfs_completed_fifo.clone()))
+}
+
+/// What is needed to manage the server in durability mode
+struct ServerLifecycle {
+    server: ServerControl,
+    readiness: ReadinessProbe,
+    scheduler: KillScheduler,
+    power_cut: Option<PowerCut>,
+}
+
+fn make_kill_worker(
+    ServerLifecycle { server, readiness, scheduler, power_cut }: ServerLifecycle,
     query_endpoint: &Url,
-    ReaderOpts { num_random_read_workers, random_read_workers_query_file }: &ReaderOpts,
-    behav: WorkerBehaviour,
+    graph_store_endpoint: &Url,
+    durability: DurabilityTracker,
+    verbose: bool,
+    history: Option<&History>,
+) -> KillWorker {
+    KillWorker::new(
+        server,
+        readiness,
+        scheduler,
+        power_cut,
+        StateReader::new(
+            Client::new(),
+            query_endpoint.clone(),
+            Some(graph_store_endpoint.clone()),
+            history.map(|history| history.recorder("kill worker")),
+        ),
+        durability,
+        verbose,
+    )
+}
+
+async fn make_random_readers(
+    ReaderOpts {
+        num_random_read_workers,
+        random_read_workers_query_file,
+        query_mix,
+        query_template,
+        bindings_csv,
+        bindings_query,
+        target_qps,
+        arrival_schedule,
+    }: &ReaderOpts,
+    config: ReaderConfig,
+    history: Option<&History>,
 ) -> anyhow::Result<Vec<RandomReadWorker>> {
+    let query_endpoint = &config.endpoint;
+
+    let target_rate = match target_qps {
+        Some(target_qps) if *target_qps <= 0.0 || !target_qps.is_finite() => {
+            anyhow::bail!("--target-qps must be a positive number")
+        },
+        Some(target_qps) => Some(TargetRate {
+            qps: target_qps / *num_random_read_workers as f64,
+            schedule: *arrival_schedule,
+        }),
+        None => None,
+    };
+
+    let query_mix = match query_mix {
+        Some(query_mix) => Some(QueryMixGenerator::new(query_mix, &Client::new(), query_endpoint).await?),
+        None => None,
+    };
+
+    let query_template = match query_template {
+        Some(query_template) => {
+            let query = std::fs::read_to_string(query_template).context("Unable to read query template")?;
+            let name = query_template
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+
+            let bindings = match (bindings_csv, bindings_query) {
+                (Some(csv_file), _) => Bindings::from_csv(csv_file)?,
+                (None, Some(bindings_query)) => {
+                    Bindings::from_select(&Client::new(), query_endpoint, bindings_query).await?
+                },
+                (None, None) => unreachable!("clap requires bindings for the query template"),
+            };
+
+            let template = QueryTemplate::new(
+                ReadQuery {
+                    name: Some(QueryId::Name(name)),
+                    query,
+                    timeout: None,
+                    expected_results: None,
+                },
+                bindings,
+            )
+            .context("Invalid query template")?;
+
+            Some(TemplateQueryGenerator::new(template))
+        },
+        None => None,
+    };
+
     let mut random_read_workers = Vec::with_capacity(*num_random_read_workers);
-    for _ in 0..*num_random_read_workers {
+    for worker_id in 1..=*num_random_read_workers {
         let query_gen: Box<dyn QueryGenerator + Send> = if let Some(query_file) = &random_read_workers_query_file {
             Box::new(FileSourceQueryGenerator::new(query_file).context("Unable to open queries file")?)
+        } else if let Some(query_mix) = &query_mix {
+            Box::new(query_mix.clone())
+        } else if let Some(query_template) = &query_template {
+            Box::new(query_template.clone())
         } else {
             Box::new(RandomLimitSelectStartQueryGenerator)
         };
 
-        let w = RandomReadWorker::new(query_gen, query_endpoint.clone(), behav);
+        let w = RandomReadWorker::new(
+            query_gen,
+            config.clone(),
+            target_rate,
+            history.map(|history| history.recorder(format!("reader {worker_id}"))),
+        );
         random_read_workers.push(w);
     }
 
@@ -397,23 +1212,19 @@ fn make_random_readers(
 }
 
 fn make_update_workers(
-    query_endpoint: &Url,
-    update_endpoint: &Url,
-    graph_store_endpoint: &Url,
-    num_update_workers: usize,
     query_dir: &Path,
-    verbose: bool,
-    behav: WorkerBehaviour,
+    num_update_workers: usize,
+    config: UpdateWorkerConfig,
+    durability: Option<&DurabilityTracker>,
+    history: Option<&History>,
 ) -> anyhow::Result<Vec<UpdateWorker>> {
     let mut update_workers = Vec::with_capacity(num_update_workers);
     for worker in 0..num_update_workers {
         let w = UpdateWorker::new(
             Path::new(&query_dir.join(format!("worker_{worker}"))),
-            query_endpoint.clone(),
-            update_endpoint.clone(),
-            graph_store_endpoint.clone(),
-            verbose,
-            behav,
+            config.clone(),
+            durability.map(|durability| durability.register(worker + 1)),
+            history.map(|history| history.recorder(format!("update worker {}", worker + 1))),
         )?;
 
         update_workers.push(w);
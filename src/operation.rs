@@ -0,0 +1,445 @@
+use crate::{
+    db_state::{DbState, StateForm},
+    Query,
+};
+use anyhow::Context;
+use oxrdf::NamedNode;
+use reqwest::StatusCode;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Formatter},
+};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Endpoint {
+    Update,
+    Gsp,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Method {
+    Post,
+    Put,
+    Delete,
+}
+
+/// The response status a request expects
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Expect {
+    /// A 2xx status
+    #[default]
+    Success,
+    /// A 4xx or 5xx status, i.e. the server must reject the request
+    Failure,
+    /// A 4xx status
+    ClientError,
+    /// A 5xx status
+    ServerError,
+}
+
+impl Expect {
+    pub fn matches(self, status: StatusCode) -> bool {
+        match self {
+            Expect::Success => status.is_success(),
+            Expect::Failure => status.is_client_error() || status.is_server_error(),
+            Expect::ClientError => status.is_client_error(),
+            Expect::ServerError => status.is_server_error(),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        *self == Expect::Success
+    }
+}
+
+/// Where the state of a validation is read from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StateSource {
+    /// A query against the query endpoint
+    Query(Query),
+    /// A CONSTRUCT query with `GRAPH` blocks in its template (not supported by every triplestore),
+    /// whose result is read as N-Quads or TriG
+    DatasetQuery(Query),
+    /// The named graphs with these IRIs, each read from the graph store endpoint like `GspGraph`
+    Graphs(Vec<String>),
+    /// The named graph with this IRI, read by a HEAD and a GET request to the graph store endpoint
+    /// (only by the GET request if the graph store does not support HEAD requests).
+    /// Unlike `Graphs`, this distinguishes a graph that does not exist from an empty graph.
+    GspGraph(String),
+}
+
+impl StateSource {
+    fn form(&self) -> anyhow::Result<StateForm> {
+        match self {
+            StateSource::Query(query) => StateForm::of_query(query).context("Invalid validation query"),
+            StateSource::DatasetQuery(_) => Ok(StateForm::Dataset),
+            StateSource::Graphs(graphs) => {
+                for graph in graphs {
+                    NamedNode::new(graph).context(format!("Invalid graph IRI {graph}"))?;
+                }
+
+                Ok(StateForm::Dataset)
+            },
+            StateSource::GspGraph(graph) => {
+                NamedNode::new(graph).context(format!("Invalid graph IRI {graph}"))?;
+                Ok(StateForm::GraphStore)
+            },
+        }
+    }
+}
+
+impl Display for StateSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateSource::Query(query) | StateSource::DatasetQuery(query) => f.write_str(query),
+            StateSource::Graphs(graphs) => {
+                f.write_str("GSP GET")?;
+
+                for graph in graphs {
+                    write!(f, " <{graph}>")?;
+                }
+
+                Ok(())
+            },
+            StateSource::GspGraph(graph) => write!(f, "GSP GET <{graph}>"),
+        }
+    }
+}
+
+/// A validation query together with its expected result.
+///
+/// The form of the expected result depends on the query: an N-Triples string for CONSTRUCT and DESCRIBE queries,
+/// a SPARQL JSON results object for SELECT queries and a boolean for ASK queries.
+/// States read as quads (`dataset_query` and `graphs`) are expected as an N-Quads string. A graph read via
+/// `gsp_graph` is expected as an N-Triples string, or as `null` if it must not exist.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(try_from = "RawValidate")]
+pub struct Validate {
+    #[serde(flatten)]
+    pub source: StateSource,
+    pub expected: DbState,
+}
+
+#[derive(Deserialize)]
+struct RawValidate {
+    #[serde(flatten)]
+    source: StateSource,
+    expected: serde_json::Value,
+}
+
+impl TryFrom<RawValidate> for Validate {
+    type Error = anyhow::Error;
+
+    fn try_from(RawValidate { source, expected }: RawValidate) -> Result<Self, Self::Error> {
+        let expected = DbState::from_json(expected, source.form()?)?;
+        Ok(Self { source, expected })
+    }
+}
+
+/// A single HTTP request to the update or graph store endpoint
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(try_from = "Map<String, Value>")]
+pub struct Request {
+    pub endpoint: Endpoint,
+    pub query_params: HashMap<String, String>,
+    pub headers: HashMap<String, String>,
+    pub method: Method,
+    pub body: String,
+    /// If the request is expected to be rejected, the state must remain unchanged,
+    /// i.e. the following validation has to expect the state before the request
+    #[serde(default, skip_serializing_if = "Expect::is_success")]
+    pub expect: Expect,
+}
+
+/// Deserializes the fields of a request one by one, so that errors name the offending field
+impl TryFrom<Map<String, Value>> for Request {
+    type Error = anyhow::Error;
+
+    fn try_from(mut fields: Map<String, Value>) -> Result<Self, Self::Error> {
+        fn field<T: DeserializeOwned>(fields: &mut Map<String, Value>, name: &str) -> anyhow::Result<Option<T>> {
+            fields
+                .remove(name)
+                .map(|value| {
+                    serde_json::from_value(value).map_err(|e| anyhow::anyhow!("invalid value of field `{name}`: {e}"))
+                })
+                .transpose()
+        }
+
+        fn required<T: DeserializeOwned>(fields: &mut Map<String, Value>, name: &str) -> anyhow::Result<T> {
+            field(fields, name)?.ok_or_else(|| anyhow::anyhow!("missing field `{name}`"))
+        }
+
+        Ok(Self {
+            endpoint: required(&mut fields, "endpoint")?,
+            query_params: required(&mut fields, "query_params")?,
+            headers: required(&mut fields, "headers")?,
+            method: required(&mut fields, "method")?,
+            body: required(&mut fields, "body")?,
+            expect: field(&mut fields, "expect")?.unwrap_or_default(),
+        })
+    }
+}
+
+/// A request of a multi-step operation, optionally followed by a validation of the intermediate state
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Step {
+    #[serde(flatten)]
+    pub request: Request,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validate: Option<Validate>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged, try_from = "RawRequests")]
+pub enum Requests {
+    /// Several requests that are issued one after the other
+    Steps {
+        steps: Vec<Step>,
+        /// If set, concurrent readers must never observe the state between two steps
+        #[serde(default)]
+        atomic: bool,
+    },
+    Single(Request),
+}
+
+/// [`Requests`] as written in an operation file. Unlike an untagged enum, this reports which field is invalid
+/// instead of only that the operation matches neither variant.
+#[derive(Deserialize)]
+struct RawRequests {
+    steps: Option<Vec<Step>>,
+    atomic: Option<bool>,
+    /// The fields of a single request
+    #[serde(flatten)]
+    request: Map<String, Value>,
+}
+
+impl TryFrom<RawRequests> for Requests {
+    type Error = anyhow::Error;
+
+    fn try_from(RawRequests { steps, atomic, request }: RawRequests) -> Result<Self, Self::Error> {
+        match steps {
+            Some(steps) => {
+                anyhow::ensure!(!steps.is_empty(), "`steps` must contain at least one step");
+                Ok(Requests::Steps { steps, atomic: atomic.unwrap_or_default() })
+            },
+            None => {
+                anyhow::ensure!(atomic.is_none(), "`atomic` is only allowed together with `steps`");
+                Ok(Requests::Single(request.try_into()?))
+            },
+        }
+    }
+}
+
+/// A single operation of an update worker, as stored in `worker_N/op_M.json`.
+///
+/// It either consists of a single request or of several `steps`. In both cases `validate` describes
+/// the state after the whole operation.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateOperation {
+    #[serde(flatten)]
+    pub requests: Requests,
+    pub validate: Validate,
+    /// How much the operation changes the values of the invariants of the workload (see [`Invariant`]),
+    /// keyed by invariant name
+    ///
+    /// [`Invariant`]: crate::invariant::Invariant
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub invariant_deltas: BTreeMap<String, i64>,
+}
+
+impl UpdateOperation {
+    pub fn single(request: Request, validate: Validate) -> Self {
+        Self {
+            requests: Requests::Single(request),
+            validate,
+            invariant_deltas: BTreeMap::new(),
+        }
+    }
+
+    /// The requests of this operation, each with the validation of the state after it.
+    /// The last request is validated by the `validate` block of the operation.
+    pub fn steps(&self) -> Vec<(&Request, Option<&Validate>)> {
+        match &self.requests {
+            Requests::Single(request) => vec![(request, Some(&self.validate))],
+            Requests::Steps { steps, .. } => {
+                let (last, intermediate) = steps.split_last().expect("operations have at least one step");
+
+                intermediate
+                    .iter()
+                    .map(|step| (&step.request, step.validate.as_ref()))
+                    .chain([(&last.request, Some(&self.validate))])
+                    .collect()
+            },
+        }
+    }
+
+    /// Whether concurrent readers must only observe the state before or after this operation
+    pub fn is_atomic(&self) -> bool {
+        match &self.requests {
+            Requests::Single(_) => true,
+            Requests::Steps { atomic, .. } => *atomic,
+        }
+    }
+
+    /// The states concurrent readers may observe in addition to the states before and after this operation,
+    /// when running the validation query of the operation.
+    ///
+    /// Returns `None` if they are unknown, i.e. if the operation is not atomic and the state after one
+    /// of the steps is not described in terms of the validation query of the operation.
+    pub fn intermediate_states(&self) -> Option<Vec<DbState>> {
+        if self.is_atomic() {
+            return Some(Vec::new());
+        }
+
+        let steps = self.steps();
+
+        steps[..steps.len() - 1]
+            .iter()
+            .map(|(_, validate)| {
+                validate
+                    .filter(|validate| validate.source == self.validate.source)
+                    .map(|validate| validate.expected.clone())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(body: &str) -> Value {
+        json!({
+            "endpoint": "UPDATE",
+            "query_params": {},
+            "headers": { "Content-type": "application/sparql-update" },
+            "method": "POST",
+            "body": body,
+        })
+    }
+
+    fn validate(query: &str, value: &str) -> Value {
+        json!({
+            "query": query,
+            "expected": format!("<http://example.org/s> <http://example.org/p> \"{value}\" .\n"),
+        })
+    }
+
+    fn validate_state(query: &str, value: &str) -> DbState {
+        serde_json::from_value::<Validate>(validate(query, value)).unwrap().expected
+    }
+
+    const QUERY: &str = "CONSTRUCT { <http://example.org/s> ?p ?o } WHERE { <http://example.org/s> ?p ?o }";
+    const OTHER_QUERY: &str = "CONSTRUCT { <http://example.org/t> ?p ?o } WHERE { <http://example.org/t> ?p ?o }";
+
+    fn step(body: &str, validate: Option<Value>) -> Value {
+        let mut step = request(body);
+
+        if let Some(validate) = validate {
+            step["validate"] = validate;
+        }
+
+        step
+    }
+
+    fn operation(value: Value) -> anyhow::Result<UpdateOperation> {
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Deserializes the operation and checks that serializing it again yields an equivalent operation
+    fn round_trip(value: Value) -> UpdateOperation {
+        let op = operation(value).unwrap();
+        let serialized = serde_json::to_value(&op).unwrap();
+        let reserialized = serde_json::to_value(operation(serialized.clone()).unwrap()).unwrap();
+
+        assert_eq!(reserialized, serialized);
+        op
+    }
+
+    #[test]
+    fn single_request_is_parsed() {
+        let mut value = request("INSERT DATA { <http://example.org/s> <http://example.org/p> \"1\" }");
+        value["validate"] = validate(QUERY, "1");
+
+        let op = round_trip(value);
+        assert!(matches!(op.requests, Requests::Single(Request { expect: Expect::Success, .. })));
+        assert!(op.is_atomic());
+        assert_eq!(op.intermediate_states(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn steps_are_parsed() {
+        let op = round_trip(json!({
+            "steps": [step("a", Some(validate(QUERY, "1"))), step("b", None)],
+            "atomic": true,
+            "validate": validate(QUERY, "2"),
+        }));
+
+        assert!(op.is_atomic());
+
+        let steps = op.steps();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].0.body, "a");
+        assert_eq!(steps[0].1.unwrap().expected, validate_state(QUERY, "1"));
+        // the last step is validated by the operation
+        assert_eq!(steps[1].1.unwrap().expected, validate_state(QUERY, "2"));
+    }
+
+    #[test]
+    fn empty_steps_are_rejected() {
+        let err = operation(json!({ "steps": [], "validate": validate(QUERY, "1") })).unwrap_err();
+        assert!(err.to_string().contains("at least one step"), "{err}");
+    }
+
+    #[test]
+    fn atomic_without_steps_is_rejected() {
+        let mut value = request("a");
+        value["atomic"] = json!(true);
+        value["validate"] = validate(QUERY, "1");
+
+        let err = operation(value).unwrap_err();
+        assert!(err.to_string().contains("`atomic` is only allowed together with `steps`"), "{err}");
+    }
+
+    #[test]
+    fn errors_name_the_invalid_field() {
+        let mut value = request("a");
+        value["method"] = json!("PATCH");
+        value["validate"] = validate(QUERY, "1");
+        let err = operation(value).unwrap_err();
+        assert!(err.to_string().contains("`method`"), "{err}");
+
+        let mut value = request("a");
+        value.as_object_mut().unwrap().remove("body");
+        value["validate"] = validate(QUERY, "1");
+        let err = operation(value).unwrap_err();
+        assert!(err.to_string().contains("missing field `body`"), "{err}");
+    }
+
+    #[test]
+    fn intermediate_states_of_non_atomic_operations() {
+        let op = |first: Option<Value>| {
+            operation(json!({
+                "steps": [step("a", first), step("b", None)],
+                "validate": validate(QUERY, "2"),
+            }))
+            .unwrap()
+        };
+
+        assert_eq!(
+            op(Some(validate(QUERY, "1"))).intermediate_states(),
+            Some(vec![validate_state(QUERY, "1")])
+        );
+
+        // the state after a step is unknown if it is not validated by the query of the operation
+        assert_eq!(op(None).intermediate_states(), None);
+        assert_eq!(op(Some(validate(OTHER_QUERY, "1"))).intermediate_states(), None);
+    }
+}
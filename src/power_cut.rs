@@ -0,0 +1,99 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::unix::pipe,
+};
+
+/// The LazyFS command that drops all data that was not synced to disk
+const CLEAR_CACHE: &str = "lazyfs::clear-cache";
+
+/// How long LazyFS may take to confirm a command
+const COMPLETION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Simulates a power loss of the disk of the server, in addition to killing its process.
+///
+/// This requires the data directory of the server to be mounted via [LazyFS](https://github.com/dsrhaslab/lazyfs),
+/// a FUSE file system that keeps written data in its own cache until it is synced. After the server was killed,
+/// LazyFS is told via its faults FIFO to drop the cached data, i.e. everything the server did not fsync is lost.
+///
+/// The server must not be running anymore at that point, otherwise it could still write data that would survive
+/// the power cut. With a kill script, the script must only return after the server process exited.
+pub struct PowerCut {
+    /// The FIFO LazyFS reads commands from (`fifo_path` in its configuration)
+    fifo: PathBuf,
+    /// The FIFO LazyFS confirms completed commands on (`fifo_path_completed` in its configuration), if any
+    completed_fifo: Option<PathBuf>,
+}
+
+impl PowerCut {
+    pub fn new(fifo: PathBuf, completed_fifo: Option<PathBuf>) -> Self {
+        Self { fifo, completed_fifo }
+    }
+
+    /// Drops the data that was not synced to disk. Must only be called while the server is not running.
+    pub async fn cut(&self) -> io::Result<()> {
+        // the completion FIFO is opened first, so that the confirmation cannot be missed
+        let completed = match &self.completed_fifo {
+            Some(path) => {
+                // opened for writing too, so that it does not block until LazyFS opens it
+                let mut file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(path)
+                    .map_err(|e| with_path(e, path))?;
+
+                discard_pending(&mut file)?;
+                Some(BufReader::new(
+                    pipe::Receiver::from_file(file).map_err(|e| with_path(e, path))?,
+                ))
+            },
+            None => None,
+        };
+
+        // fails instead of blocking if LazyFS is not reading from the FIFO
+        let mut sender = pipe::OpenOptions::new().open_sender(&self.fifo).map_err(|e| {
+            if e.raw_os_error() == Some(libc::ENXIO) {
+                io::Error::other(format!("{}: LazyFS is not reading from the FIFO", self.fifo.display()))
+            } else {
+                with_path(e, &self.fifo)
+            }
+        })?;
+        sender.write_all(format!("{CLEAR_CACHE}\n").as_bytes()).await?;
+
+        if let Some(mut completed) = completed {
+            let mut line = String::new();
+
+            tokio::time::timeout(COMPLETION_TIMEOUT, completed.read_line(&mut line))
+                .await
+                .map_err(|_| io::Error::other("LazyFS did not confirm that it dropped the unsynced data"))??;
+        }
+
+        Ok(())
+    }
+}
+
+/// Drops confirmations of earlier commands (e.g. of an earlier test run) that were never read,
+/// so that they are not mistaken for the confirmation of the next command
+fn discard_pending(file: &mut File) -> io::Result<()> {
+    let mut buf = [0; 1024];
+
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn with_path(e: io::Error, path: &Path) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {e}", path.display()))
+}
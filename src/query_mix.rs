@@ -0,0 +1,140 @@
+use crate::{
+    query_template::{Bindings, QueryTemplate, TemplateQueryGenerator},
+    random_read_worker::{QueryGenerator, QueryId, ReadQuery},
+    Query,
+};
+use anyhow::Context;
+use rand::distr::{weighted::WeightedIndex, Distribution};
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// A query of a query mix file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MixEntry {
+    /// Name the statistics of the query are reported under
+    name: String,
+    /// The query itself, mutually exclusive with `query_file`
+    query: Option<Query>,
+    /// File containing the query, relative to the query mix file
+    query_file: Option<PathBuf>,
+    /// Relative frequency of the query
+    #[serde(default = "default_weight")]
+    weight: f64,
+    timeout_ms: Option<u64>,
+    expected_results: Option<usize>,
+    /// If present, the query is a template whose `%name%` placeholders are filled from these bindings
+    bindings: Option<BindingsSource>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum BindingsSource {
+    /// CSV file, relative to the query mix file
+    Csv(PathBuf),
+    /// SPARQL SELECT query that is run once against the query endpoint before the test starts
+    Query(Query),
+}
+
+#[derive(Clone)]
+enum MixQuery {
+    Fixed(Arc<ReadQuery>),
+    Template(TemplateQueryGenerator),
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+/// Samples the queries of a query mix file according to their weights.
+///
+/// The file is a JSON array of queries, e.g.
+/// ```json
+/// [
+///     { "name": "lookup", "weight": 8, "query": "SELECT ...", "timeout_ms": 500, "expected_results": 1 },
+///     { "name": "analytics", "weight": 2, "query_file": "analytics.rq" },
+///     { "name": "by_subject", "query": "DESCRIBE %s%", "bindings": { "csv": "subjects.csv" } }
+/// ]
+/// ```
+#[derive(Clone)]
+pub struct QueryMixGenerator {
+    queries: Vec<MixQuery>,
+    weights: WeightedIndex<f64>,
+}
+
+impl QueryMixGenerator {
+    /// Loads a query mix. The bindings queries of query templates are run against `query_endpoint`.
+    pub async fn new(mix_file: &Path, client: &Client, query_endpoint: &Url) -> anyhow::Result<Self> {
+        let file = File::open(mix_file).context("Unable to open query mix file")?;
+        let entries: Vec<MixEntry> = serde_json::from_reader(file).context("Unable to deserialize query mix")?;
+
+        anyhow::ensure!(!entries.is_empty(), "The query mix does not contain any queries");
+
+        let base_dir = mix_file.parent().unwrap_or(Path::new(""));
+        let mut names = HashSet::new();
+        let mut queries = Vec::with_capacity(entries.len());
+        let mut weights = Vec::with_capacity(entries.len());
+
+        for entry in entries {
+            anyhow::ensure!(names.insert(entry.name.clone()), "Duplicate query name {}", entry.name);
+
+            let query = match (entry.query, entry.query_file) {
+                (Some(query), None) => query,
+                (None, Some(query_file)) => std::fs::read_to_string(base_dir.join(&query_file)).context(format!(
+                    "Unable to read query file {} of query {}",
+                    query_file.display(),
+                    entry.name
+                ))?,
+                _ => anyhow::bail!("Query {} must have exactly one of `query` and `query_file`", entry.name),
+            };
+
+            let read_query = ReadQuery {
+                name: Some(QueryId::Name(entry.name.clone())),
+                query,
+                timeout: entry.timeout_ms.map(Duration::from_millis),
+                expected_results: entry.expected_results,
+            };
+
+            let mix_query = match entry.bindings {
+                None => MixQuery::Fixed(Arc::new(read_query)),
+                Some(source) => {
+                    let bindings = match source {
+                        BindingsSource::Csv(csv_file) => Bindings::from_csv(&base_dir.join(csv_file)),
+                        BindingsSource::Query(bindings_query) => {
+                            Bindings::from_select(client, query_endpoint, &bindings_query).await
+                        },
+                    }
+                    .context(format!("Unable to load the bindings of query {}", entry.name))?;
+
+                    let template =
+                        QueryTemplate::new(read_query, bindings).context(format!("Invalid template {}", entry.name))?;
+
+                    MixQuery::Template(TemplateQueryGenerator::new(template))
+                },
+            };
+
+            queries.push(mix_query);
+            weights.push(entry.weight);
+        }
+
+        let weights = WeightedIndex::new(weights).context("Invalid query weights")?;
+
+        Ok(Self { queries, weights })
+    }
+}
+
+impl QueryGenerator for QueryMixGenerator {
+    fn next_query(&mut self) -> Arc<ReadQuery> {
+        match &mut self.queries[self.weights.sample(&mut rand::rng())] {
+            MixQuery::Fixed(query) => query.clone(),
+            MixQuery::Template(template) => template.next_query(),
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use crate::{
+    random_read_worker::{QueryGenerator, ReadQuery},
+    Query,
+};
+use anyhow::Context;
+use oxrdf::Term;
+use rand::Rng;
+use reqwest::{header::ACCEPT, Client, Url};
+use sparesults::{QueryResultsFormat, QueryResultsParser, SliceQueryResultsParserOutput};
+use std::{path::Path, sync::Arc};
+
+/// Values for the placeholders of a query template, one row per instantiation
+pub struct Bindings {
+    variables: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Bindings {
+    /// Reads bindings from a CSV file. The header contains the placeholder names,
+    /// the values are inserted as they are, so they must be written in SPARQL syntax (e.g. `<http://example.org/s>`).
+    pub fn from_csv(csv_file: &Path) -> anyhow::Result<Self> {
+        let mut reader = csv::Reader::from_path(csv_file).context("Unable to open bindings file")?;
+
+        let variables = reader
+            .headers()
+            .context("Unable to read bindings header")?
+            .iter()
+            .map(ToOwned::to_owned)
+            .collect();
+
+        let rows = reader
+            .records()
+            .map(|record| Ok(record?.iter().map(ToOwned::to_owned).collect()))
+            .collect::<Result<_, csv::Error>>()
+            .context("Unable to read bindings")?;
+
+        Self::new(variables, rows)
+    }
+
+    /// Runs a SPARQL SELECT query and uses its solutions as bindings. The placeholders are named after the variables.
+    pub async fn from_select(client: &Client, endpoint: &Url, query: &Query) -> anyhow::Result<Self> {
+        let body = client
+            .get(endpoint.clone())
+            .query(&[("query", query)])
+            .header(ACCEPT, "application/sparql-results+json")
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .context("Unable to execute bindings query")?
+            .bytes()
+            .await
+            .context("Unable to read bindings query result")?;
+
+        let SliceQueryResultsParserOutput::Solutions(solutions) =
+            QueryResultsParser::from_format(QueryResultsFormat::Json)
+                .for_slice(&body)
+                .context("Unable to parse bindings query result")?
+        else {
+            anyhow::bail!("The bindings query must be a SELECT query");
+        };
+
+        let variables = solutions.variables().iter().map(|v| v.as_str().to_owned()).collect();
+        let mut rows = Vec::new();
+
+        for solution in solutions {
+            let solution = solution.context("Unable to parse bindings query result")?;
+
+            let row = solution
+                .values()
+                .iter()
+                .map(|value| match value {
+                    Some(Term::BlankNode(_)) => anyhow::bail!("Blank nodes cannot be used as query parameters"),
+                    Some(term) => Ok(term.to_string()),
+                    None => anyhow::bail!("All variables of the bindings query must be bound"),
+                })
+                .collect::<anyhow::Result<_>>()?;
+
+            rows.push(row);
+        }
+
+        Self::new(variables, rows)
+    }
+
+    fn new(variables: Vec<String>, rows: Vec<Vec<String>>) -> anyhow::Result<Self> {
+        anyhow::ensure!(!rows.is_empty(), "There are no bindings");
+        Ok(Self { variables, rows })
+    }
+}
+
+/// A query with `%name%` placeholders that are replaced by a random row of bindings every time it is instantiated
+pub struct QueryTemplate {
+    template: ReadQuery,
+    /// The query split at its placeholders
+    segments: Vec<Segment>,
+    bindings: Bindings,
+}
+
+enum Segment {
+    Text(String),
+    /// A placeholder, by the index of its variable in the bindings
+    Placeholder(usize),
+}
+
+impl QueryTemplate {
+    /// Placeholders are only recognized outside of string literals, IRIs and comments, so that e.g. `"50%"` is left
+    /// as it is. Every placeholder of the template must have bindings and vice versa. A `%` that is followed by two
+    /// hex digits and another `%` (e.g. `%C3%A9` in a prefixed name) is only a placeholder if there are bindings for it.
+    pub fn new(template: ReadQuery, bindings: Bindings) -> anyhow::Result<Self> {
+        let mut segments = Vec::new();
+        let mut used = vec![false; bindings.variables.len()];
+        let mut text = String::new();
+
+        for (is_quoted, part) in split_quoted(&template.query) {
+            if is_quoted {
+                text.push_str(part);
+                continue;
+            }
+
+            let mut rest = part;
+
+            while let Some(start) = rest.find('%') {
+                text.push_str(&rest[..start]);
+                rest = &rest[start + 1..];
+
+                let name_len = rest
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                let name = &rest[..name_len];
+
+                if !name.is_empty() && rest[name_len..].starts_with('%') {
+                    if let Some(var) = bindings.variables.iter().position(|var| var == name) {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                        segments.push(Segment::Placeholder(var));
+                        used[var] = true;
+                        rest = &rest[name_len + 1..];
+                        continue;
+                    }
+
+                    let is_percent_encoded = name.len() == 2 && name.chars().all(|c| c.is_ascii_hexdigit());
+                    anyhow::ensure!(is_percent_encoded, "There are no bindings for the placeholder %{name}%");
+                }
+
+                text.push('%');
+            }
+
+            text.push_str(rest);
+        }
+
+        segments.push(Segment::Text(text));
+
+        if let Some(unused) = used.iter().position(|used| !used) {
+            anyhow::bail!(
+                "The template does not contain the placeholder %{}%",
+                bindings.variables[unused]
+            );
+        }
+
+        Ok(Self { template, segments, bindings })
+    }
+
+    /// Replaces the placeholders in a single pass, so values that look like placeholders are inserted as they are
+    pub fn instantiate(&self) -> ReadQuery {
+        let row = &self.bindings.rows[rand::rng().random_range(0..self.bindings.rows.len())];
+
+        let query = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.as_str(),
+                Segment::Placeholder(var) => row[*var].as_str(),
+            })
+            .collect();
+
+        ReadQuery {
+            name: self.template.name.clone(),
+            query,
+            timeout: self.template.timeout,
+            expected_results: self.template.expected_results,
+        }
+    }
+}
+
+/// Sends instances of a single query template, e.g. the one given by `--query-template`
+#[derive(Clone)]
+pub struct TemplateQueryGenerator {
+    template: Arc<QueryTemplate>,
+}
+
+impl TemplateQueryGenerator {
+    pub fn new(template: QueryTemplate) -> Self {
+        Self { template: Arc::new(template) }
+    }
+}
+
+impl QueryGenerator for TemplateQueryGenerator {
+    fn next_query(&mut self) -> Arc<ReadQuery> {
+        Arc::new(self.template.instantiate())
+    }
+}
+
+/// Splits a query into the parts outside of string literals, IRIs and comments (`false`) and those parts (`true`)
+fn split_quoted(query: &str) -> Vec<(bool, &str)> {
+    let bytes = query.as_bytes();
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let end = match bytes[i] {
+            quote @ (b'"' | b'\'') => {
+                let long = bytes[i..].starts_with(&[quote; 3]);
+                let mut j = if long { i + 3 } else { i + 1 };
+
+                loop {
+                    match bytes.get(j) {
+                        None => break j,
+                        Some(b'\\') => j += 2,
+                        Some(_) if long && bytes[j..].starts_with(&[quote; 3]) => break j + 3,
+                        Some(&c) if !long && c == quote => break j + 1,
+                        Some(_) => j += 1,
+                    }
+                }
+            },
+            b'<' => match bytes[i + 1..].iter().position(|c| !is_iri_char(*c)) {
+                Some(len) if bytes[i + 1 + len] == b'>' => i + len + 2,
+                _ => {
+                    i += 1;
+                    continue;
+                },
+            },
+            b'#' => bytes[i..]
+                .iter()
+                .position(|c| *c == b'\n')
+                .map_or(bytes.len(), |len| i + len),
+            _ => {
+                i += 1;
+                continue;
+            },
+        }
+        .min(bytes.len());
+
+        parts.push((false, &query[start..i]));
+        parts.push((true, &query[i..end]));
+        start = end;
+        i = end;
+    }
+
+    parts.push((false, &query[start..]));
+    parts
+}
+
+/// Whether the character may appear in an IRI reference (`IRIREF` in the SPARQL grammar)
+fn is_iri_char(c: u8) -> bool {
+    !matches!(
+        c,
+        b'<' | b'>' | b'"' | b'{' | b'}' | b'|' | b'^' | b'`' | b'\\' | 0..=b' '
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(query: &str, bindings: &[(&str, &str)]) -> anyhow::Result<QueryTemplate> {
+        let bindings = Bindings::new(
+            bindings.iter().map(|(var, _)| (*var).to_owned()).collect(),
+            vec![bindings.iter().map(|(_, value)| (*value).to_owned()).collect()],
+        )?;
+
+        QueryTemplate::new(
+            ReadQuery { name: None, query: query.to_owned(), timeout: None, expected_results: None },
+            bindings,
+        )
+    }
+
+    fn instantiate(query: &str, bindings: &[(&str, &str)]) -> String {
+        template(query, bindings).unwrap().instantiate().query
+    }
+
+    #[test]
+    fn placeholders_are_replaced() {
+        assert_eq!(
+            instantiate(
+                "SELECT * WHERE { %s% ?p %o% . %s% ?q ?r }",
+                &[("s", "<http://example.org/s>"), ("o", "\"o\"@en")]
+            ),
+            "SELECT * WHERE { <http://example.org/s> ?p \"o\"@en . <http://example.org/s> ?q ?r }"
+        );
+    }
+
+    #[test]
+    fn values_are_inserted_as_they_are() {
+        assert_eq!(
+            instantiate(
+                "SELECT * WHERE { %s% ?p %o% }",
+                &[("s", "%o%"), ("o", "<http://example.org/o>")]
+            ),
+            "SELECT * WHERE { %o% ?p <http://example.org/o> }"
+        );
+    }
+
+    #[test]
+    fn percent_signs_in_literals_iris_and_comments_are_not_placeholders() {
+        let queries = [
+            "SELECT * WHERE { %s% ?p ?x FILTER(?x = \"50%\") }",
+            "SELECT * WHERE { %s% ?p ?x FILTER(regex(?x, \"%a%\")) }",
+            "SELECT * WHERE { %s% ?p ?x FILTER(regex(?x, '%a%') || ?x = \"\"\"%a% \" %b%\"\"\") }",
+            "SELECT * WHERE { %s% ?p ?x FILTER(?x = \"\\\"%a%\") }",
+            "SELECT * WHERE { %s% ?p <http://example.org/%a%> }",
+            "# 100% of %a%\nSELECT * WHERE { %s% ?p ?o }",
+            "PREFIX ex: <http://example.org/> SELECT * WHERE { %s% ?p ex:caf%C3%A9 }",
+        ];
+
+        for query in queries {
+            assert_eq!(
+                instantiate(query, &[("s", "<http://example.org/s>")]),
+                query.replace("%s%", "<http://example.org/s>"),
+                "{query}"
+            );
+        }
+    }
+
+    #[test]
+    fn comparisons_are_not_iris() {
+        assert_eq!(
+            instantiate(
+                "SELECT * WHERE { ?s ?p ?o FILTER(?o < %min% && ?o > 3) }",
+                &[("min", "5")]
+            ),
+            "SELECT * WHERE { ?s ?p ?o FILTER(?o < 5 && ?o > 3) }"
+        );
+    }
+
+    #[test]
+    fn placeholders_without_bindings_are_rejected() {
+        assert!(template("SELECT * WHERE { %s% ?p %o% }", &[("s", "<http://example.org/s>")]).is_err());
+    }
+
+    #[test]
+    fn bindings_without_placeholders_are_rejected() {
+        assert!(template(
+            "SELECT * WHERE { %s% ?p \"%o%\" }",
+            &[("s", "<http://example.org/s>"), ("o", "<http://example.org/o>")]
+        )
+        .is_err());
+    }
+}
@@ -1,33 +1,101 @@
-use crate::{error::WorkerError, Qps, Query, WorkerBehaviour};
-use rand::{seq::SliceRandom, Rng};
-use reqwest::{Client, Response, Url};
+use crate::{
+    db_state::StateForm,
+    durability::RestartGate,
+    error::{InvariantVerboseInfo, ReadStateError, TransitionVerboseInfo, WorkerError},
+    history::HistoryRecorder,
+    invariant::Invariant,
+    latency::LatencyHistogram,
+    operation::StateSource,
+    report::unix_time_s,
+    update_worker::{InFlightReceiver, StateReader},
+    AvgQps, Qps, Query, WorkerBehaviour,
+};
+use clap::ValueEnum;
+use rand::{
+    seq::{IndexedRandom, SliceRandom},
+    Rng,
+};
+use reqwest::{
+    header::{ACCEPT, CONTENT_TYPE},
+    Client, Url,
+};
+use serde::{de::IgnoredAny, Deserialize, Serialize, Serializer};
 use std::{
-    borrow::Cow,
-    collections::BTreeMap,
-    future::Future,
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Formatter},
     io,
+    ops::ControlFlow,
     path::Path,
+    sync::Arc,
     time::{Duration, Instant},
 };
+use tokio::task::{self, JoinSet};
+
+/// Name the statistics of a query are reported under
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QueryId {
+    /// The (0-based) line index of a query in a query file, so that the statistics are sorted by line
+    Line(usize),
+    /// The name of a query in a query mix
+    Name(String),
+}
+
+impl Display for QueryId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryId::Line(ix) => write!(f, "{ix}"),
+            QueryId::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+impl Serialize for QueryId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A query a reader sends, along with what is expected of its answer
+#[derive(Debug)]
+pub struct ReadQuery {
+    /// Name the statistics of the query are reported under.
+    /// Queries without a name only count towards the statistics over all queries.
+    pub name: Option<QueryId>,
+    pub query: Query,
+    /// Time after which the query is abandoned and counted as timed out
+    pub timeout: Option<Duration>,
+    /// Number of solutions (or triples for CONSTRUCT and DESCRIBE queries) the answer must contain
+    pub expected_results: Option<usize>,
+}
+
+impl ReadQuery {
+    fn new(name: Option<QueryId>, query: Query) -> Self {
+        Self { name, query, timeout: None, expected_results: None }
+    }
+}
 
 pub trait QueryGenerator {
-    fn next_query(&mut self) -> (Option<usize>, Cow<str>);
+    fn next_query(&mut self) -> Arc<ReadQuery>;
 }
 
 #[derive(Copy, Clone)]
 pub struct RandomLimitSelectStartQueryGenerator;
 
 impl QueryGenerator for RandomLimitSelectStartQueryGenerator {
-    fn next_query(&mut self) -> (Option<usize>, Cow<'_, str>) {
+    fn next_query(&mut self) -> Arc<ReadQuery> {
         let limit = rand::rng().random_range(200..500);
-        (None, Cow::Owned(format!("SELECT * WHERE {{ ?s ?p ?o }} LIMIT {limit}")))
+        Arc::new(ReadQuery::new(
+            None,
+            format!("SELECT * WHERE {{ ?s ?p ?o }} LIMIT {limit}"),
+        ))
     }
 }
 
+/// Cycles through the queries of a file (one query per line) in random order.
+/// The queries are named after their (0-based) line index, ignoring empty lines.
 #[derive(Clone)]
 pub struct FileSourceQueryGenerator {
-    queries_original_order: Vec<Query>,
-    queries: Vec<Query>,
+    queries: Vec<Arc<ReadQuery>>,
     ix: usize,
 }
 
@@ -38,15 +106,16 @@ impl FileSourceQueryGenerator {
         let queries: Vec<_> = std::fs::read_to_string(query_file)?
             .lines()
             .filter(|l| !l.is_empty())
-            .map(ToOwned::to_owned)
+            .enumerate()
+            .map(|(ix, query)| Arc::new(ReadQuery::new(Some(QueryId::Line(ix)), query.to_owned())))
             .collect();
 
-        Ok(Self { queries_original_order: queries.clone(), queries, ix: 0 })
+        Ok(Self { queries, ix: 0 })
     }
 }
 
 impl QueryGenerator for FileSourceQueryGenerator {
-    fn next_query(&mut self) -> (Option<usize>, Cow<'_, str>) {
+    fn next_query(&mut self) -> Arc<ReadQuery> {
         let cur_ix = self.ix;
         if cur_ix == 0 {
             self.queries.shuffle(&mut rand::rng());
@@ -54,88 +123,579 @@ impl QueryGenerator for FileSourceQueryGenerator {
 
         self.ix = (self.ix + 1) % self.queries.len();
 
-        let ret_query = &self.queries[cur_ix];
-        let orig_ix = self.queries_original_order.iter().position(|q| q == ret_query).unwrap();
+        self.queries[cur_ix].clone()
+    }
+}
+
+/// The latencies and outcomes of the queries (with a certain name) a reader sent
+#[derive(Default)]
+pub struct QueryStats {
+    /// Latencies of the answered queries, the timed out ones (i.e. at least their timeout) and the unfinished ones
+    pub latencies: LatencyHistogram,
+    pub n_answered: u64,
+    /// Number of queries that were abandoned after their timeout
+    pub n_timeouts: u64,
+    /// Number of queries that were still unanswered when the reader was stopped.
+    /// Their latency is recorded as the time until then, i.e. a lower bound.
+    pub n_unfinished: u64,
+}
+
+impl QueryStats {
+    fn record(&mut self, latency: Duration) {
+        self.latencies.record(latency);
+        self.n_answered += 1;
+    }
+
+    fn record_timeout(&mut self, latency: Duration) {
+        self.latencies.record(latency);
+        self.n_timeouts += 1;
+    }
+
+    fn record_unfinished(&mut self, latency: Duration) {
+        self.latencies.record(latency);
+        self.n_unfinished += 1;
+    }
+}
+
+/// The query latencies a reader measured
+#[derive(Default)]
+pub struct ReadMeasurements {
+    /// Statistics of the named queries, per query name
+    pub per_query: BTreeMap<QueryId, QueryStats>,
+    /// Statistics of all queries
+    pub all: QueryStats,
+    /// How long an open-loop reader ran, `None` for a closed-loop reader
+    pub open_loop_duration: Option<Duration>,
+}
+
+impl ReadMeasurements {
+    /// Queries per second of every named query. For a closed-loop reader this is derived from the mean latency,
+    /// i.e. the rate at which the query could be sent one after the other. For an open-loop reader the rate
+    /// is fixed, so this is the number of answered queries per second instead.
+    pub fn per_query_qps(&self) -> BTreeMap<QueryId, Qps> {
+        self.per_query
+            .iter()
+            .map(|(query_name, stats)| (query_name.clone(), self.qps(stats)))
+            .collect()
+    }
+
+    /// The average of [`Self::per_query_qps`] for a closed-loop reader (or the QPS over all queries if it did not
+    /// send any named queries), the number of answered queries per second over all queries for an open-loop reader
+    pub fn avg_qps(&self) -> AvgQps {
+        match self.open_loop_duration {
+            None if !self.per_query.is_empty() => {
+                self.per_query.values().map(|stats| self.qps(stats)).sum::<Qps>() / self.per_query.len() as f64
+            },
+            _ => self.qps(&self.all),
+        }
+    }
+
+    fn qps(&self, stats: &QueryStats) -> Qps {
+        match self.open_loop_duration {
+            Some(duration) => stats.n_answered as f64 / duration.as_secs_f64(),
+            None => stats.latencies.qps(),
+        }
+    }
+
+    fn record(&mut self, query: &ReadQuery, outcome: QueryOutcome) {
+        match outcome {
+            QueryOutcome::Answered(latency) => {
+                self.all.record(latency);
+
+                if let Some(name) = &query.name {
+                    self.per_query.entry(name.clone()).or_default().record(latency);
+                }
+            },
+            QueryOutcome::TimedOut(latency) => {
+                self.all.record_timeout(latency);
+
+                if let Some(name) = &query.name {
+                    self.per_query.entry(name.clone()).or_default().record_timeout(latency);
+                }
+            },
+            QueryOutcome::Unavailable => (),
+        }
+    }
+
+    fn record_unfinished(&mut self, query: &ReadQuery, latency: Duration) {
+        self.all.record_unfinished(latency);
+
+        if let Some(name) = &query.name {
+            self.per_query
+                .entry(name.clone())
+                .or_default()
+                .record_unfinished(latency);
+        }
+    }
+}
+
+enum QueryOutcome {
+    /// The query was answered after the given time
+    Answered(Duration),
+    /// The query was abandoned after its timeout, the given time after it was (scheduled to be) sent
+    TimedOut(Duration),
+    /// The server could not be reached, which is expected while it is being restarted
+    Unavailable,
+}
+
+/// Response format of SPARQL SELECT and ASK queries, only as far as needed to count the solutions
+#[derive(Deserialize)]
+struct JsonResults {
+    results: Option<JsonBindings>,
+    boolean: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct JsonBindings {
+    bindings: Vec<IgnoredAny>,
+}
+
+/// Number of solutions (SPARQL JSON results) or triples (N-Triples) in a response.
+/// The answer of an ASK query counts as a single solution if it is true and as none if it is false.
+fn count_results(content_type: &str, body: &str) -> Result<usize, ReadStateError> {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+
+    match media_type {
+        "application/sparql-results+json" | "application/json" => match serde_json::from_str(body)? {
+            JsonResults { results: Some(results), .. } => Ok(results.bindings.len()),
+            JsonResults { boolean: Some(boolean), .. } => Ok(boolean as usize),
+            JsonResults { results: None, boolean: None } => {
+                Err(ReadStateError::UnexpectedResultForm("solutions or a boolean"))
+            },
+        },
+        "application/n-triples" | "text/plain" => {
+            let mut n_triples = 0;
+            for triple in oxttl::NTriplesParser::new().for_slice(body.as_bytes()) {
+                triple?;
+                n_triples += 1;
+            }
+
+            Ok(n_triples)
+        },
+        _ => Err(ReadStateError::UnsupportedFormat(content_type.to_owned())),
+    }
+}
+
+/// Sends `query` and measures the time from `start` until the response was fully received
+async fn send_query(
+    client: &Client,
+    endpoint: &Url,
+    behav: WorkerBehaviour,
+    start: Instant,
+    query: &ReadQuery,
+) -> Result<QueryOutcome, WorkerError> {
+    let mut request = client.get(endpoint.clone()).query(&[("query", &query.query)]);
+
+    if let Some(timeout) = query.timeout {
+        request = request.timeout(timeout);
+    }
+
+    if query.expected_results.is_some() {
+        request = request.header(ACCEPT, "application/sparql-results+json, application/n-triples;q=0.9");
+    }
+
+    let response = async {
+        let resp = request.send().await?.error_for_status()?;
+
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+
+        Ok::<_, reqwest::Error>((content_type, resp.text().await?))
+    };
+
+    let (content_type, body) = match response.await {
+        Ok(response) => response,
+        Err(e) if e.is_timeout() && query.timeout.is_some() => return Ok(QueryOutcome::TimedOut(start.elapsed())),
+        Err(e) if !e.is_status() && behav == WorkerBehaviour::IgnoreConnectionError => {
+            return Ok(QueryOutcome::Unavailable)
+        },
+        Err(e) => return Err(WorkerError::ReadFailed { query: query.query.clone(), err: e.into() }),
+    };
+
+    let latency = start.elapsed();
+
+    if let Some(expected) = query.expected_results {
+        let actual = count_results(&content_type, &body)
+            .map_err(|err| WorkerError::ReadFailed { query: query.query.clone(), err })?;
+
+        if actual != expected {
+            return Err(WorkerError::UnexpectedResultCount {
+                query_name: query
+                    .name
+                    .as_ref()
+                    .map_or_else(|| query.query.clone(), ToString::to_string),
+                expected,
+                actual,
+            });
+        }
+    } else {
+        std::hint::black_box(body);
+    }
+
+    Ok(QueryOutcome::Answered(latency))
+}
+
+/// How the queries of an open-loop reader are spaced
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArrivalSchedule {
+    /// Queries are sent at a fixed interval
+    Fixed,
+    /// The intervals between queries are exponentially distributed, i.e. the queries arrive as a Poisson process
+    Poisson,
+}
 
-        (Some(orig_ix), Cow::Borrowed(ret_query))
+/// The rate at which an open-loop reader sends queries, independent of how fast they are answered
+#[derive(Copy, Clone)]
+pub struct TargetRate {
+    pub qps: f64,
+    pub schedule: ArrivalSchedule,
+}
+
+impl TargetRate {
+    fn next_interval(&self) -> Duration {
+        let mean_interval_s = 1.0 / self.qps;
+
+        match self.schedule {
+            ArrivalSchedule::Fixed => Duration::from_secs_f64(mean_interval_s),
+            ArrivalSchedule::Poisson => {
+                let u: f64 = rand::rng().random();
+                Duration::from_secs_f64(-mean_interval_s * (1.0 - u).ln())
+            },
+        }
     }
 }
 
+/// Checks the state of the in-flight operation of a single update worker
+struct IsolationProbe {
+    state_reader: StateReader,
+    behav: WorkerBehaviour,
+    verbose: bool,
+    update_worker_id: usize,
+    in_flight: InFlightReceiver,
+}
+
+impl IsolationProbe {
+    /// Runs the validation query of the in-flight operation and checks that the observed state
+    /// is either the state before or after that operation, never a mix.
+    async fn run(self) -> Result<(), WorkerError> {
+        let Some(op) = self.in_flight.borrow().clone() else {
+            return Ok(());
+        };
+
+        let actual = match self
+            .state_reader
+            .fetch(&op.source, op.pre_state.form(), self.behav)
+            .await
+        {
+            Ok(ControlFlow::Break(state)) => state,
+            Ok(ControlFlow::Continue(())) => return Ok(()),
+            Err(err) => return Err(WorkerError::ReadFailed { query: op.source.to_string(), err }),
+        };
+
+        // if the update worker moved on while we were reading, the observed state
+        // may already contain (parts of) the next operation and cannot be judged
+        let still_in_flight = self
+            .in_flight
+            .borrow()
+            .as_ref()
+            .is_some_and(|cur| Arc::ptr_eq(cur, &op));
+
+        if !still_in_flight
+            || actual == op.pre_state
+            || actual == op.post_state
+            || op.intermediate_states.contains(&actual)
+        {
+            return Ok(());
+        }
+
+        Err(WorkerError::IsolationViolation {
+            update_worker_id: self.update_worker_id,
+            update_id: op.update_id,
+            verbose_info: if self.verbose {
+                Some(TransitionVerboseInfo {
+                    query: op.source.to_string(),
+                    pre_state: op.pre_state.to_string(),
+                    post_state: op.post_state.to_string(),
+                    actual: actual.to_string(),
+                })
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// Checks that the value of a conserved invariant equals its initial value
+struct ConservationProbe {
+    state_reader: StateReader,
+    behav: WorkerBehaviour,
+    verbose: bool,
+    invariant: Arc<Invariant>,
+}
+
+impl ConservationProbe {
+    async fn run(self) -> Result<(), WorkerError> {
+        let source = StateSource::Query(self.invariant.query.clone());
+
+        let actual = match self
+            .state_reader
+            .fetch(&source, StateForm::Solutions { ordered: false }, self.behav)
+            .await
+        {
+            Ok(ControlFlow::Break(state)) => state,
+            Ok(ControlFlow::Continue(())) => return Ok(()),
+            Err(err) => return Err(WorkerError::ReadFailed { query: self.invariant.query.clone(), err }),
+        };
+
+        let observed_at_unix_s = unix_time_s();
+
+        match actual.as_integer() {
+            Some(value) if value == self.invariant.initial => Ok(()),
+            value => Err(WorkerError::ConservationViolation {
+                invariant: self.invariant.name.clone(),
+                expected: self.invariant.initial,
+                actual: value,
+                observed_at_unix_s,
+                verbose_info: if self.verbose {
+                    Some(InvariantVerboseInfo { query: self.invariant.query.clone(), actual: actual.to_string() })
+                } else {
+                    None
+                },
+            }),
+        }
+    }
+}
+
+/// The configuration shared by all random readers of a test
+#[derive(Clone)]
+pub struct ReaderConfig {
+    pub endpoint: Url,
+    /// Only needed to probe operations that are validated via the graph store protocol
+    pub graph_store_endpoint: Option<Url>,
+    pub behav: WorkerBehaviour,
+    /// The in-flight operations of the update workers (keyed by update worker id) that the readers should check
+    /// for isolation violations. It is empty if there are no concurrent updates.
+    pub in_flight_updates: Vec<(usize, InFlightReceiver)>,
+    /// Invariants whose values are checked after every query as well
+    pub conserved: Vec<Arc<Invariant>>,
+    /// If the server cannot be reached, the readers wait at this gate before they send the next query
+    pub restart_gate: Option<RestartGate>,
+    pub verbose: bool,
+}
+
 pub struct RandomReadWorker {
     endpoint: Url,
     client: Client,
+    state_reader: StateReader,
     query_gen: Box<dyn QueryGenerator + Send>,
     behav: WorkerBehaviour,
+    in_flight_updates: Vec<(usize, InFlightReceiver)>,
+    conserved: Vec<Arc<Invariant>>,
+    target_rate: Option<TargetRate>,
+    restart_gate: Option<RestartGate>,
+    verbose: bool,
 }
 
 impl RandomReadWorker {
-    pub fn new(query_gen: Box<dyn QueryGenerator + Send>, endpoint: Url, behav: WorkerBehaviour) -> Self {
+    /// Without a `target_rate` the reader sends the next query as soon as the previous one was answered.
+    /// If a `history` is given, the states observed by the isolation probes are recorded in it.
+    pub fn new(
+        query_gen: Box<dyn QueryGenerator + Send>,
+        ReaderConfig {
+            endpoint,
+            graph_store_endpoint,
+            behav,
+            in_flight_updates,
+            conserved,
+            restart_gate,
+            verbose,
+        }: ReaderConfig,
+        target_rate: Option<TargetRate>,
+        history: Option<HistoryRecorder>,
+    ) -> Self {
         let client = Client::builder().tcp_nodelay(true).build().unwrap();
 
-        Self { endpoint, client, query_gen, behav }
+        Self {
+            state_reader: StateReader::new(client.clone(), endpoint.clone(), graph_store_endpoint, history),
+            endpoint,
+            client,
+            query_gen,
+            behav,
+            in_flight_updates,
+            conserved,
+            target_rate,
+            restart_gate,
+            verbose,
+        }
     }
 
-    async fn measure_query(
-        behav: WorkerBehaviour,
-        query: impl Future<Output = reqwest::Result<Response>>,
-    ) -> reqwest::Result<Option<Duration>> {
-        let start = Instant::now();
+    /// An isolation probe for the in-flight operation of a random update worker, if there are concurrent updates
+    fn isolation_probe(&self) -> Option<IsolationProbe> {
+        if self.in_flight_updates.is_empty() {
+            return None;
+        }
 
-        match query.await {
-            Ok(resp) => {
-                let resp = resp.error_for_status()?;
-
-                match resp.text().await {
-                    Ok(text) => {
-                        std::hint::black_box(text);
-                        let end = Instant::now();
-                        Ok(Some(end.duration_since(start)))
-                    },
-                    Err(_) if behav == WorkerBehaviour::IgnoreConnectionError => Ok(None),
-                    Err(e) => Err(e),
-                }
-            },
-            Err(_) if behav == WorkerBehaviour::IgnoreConnectionError => Ok(None),
-            Err(e) => Err(e),
+        let (update_worker_id, in_flight) =
+            &self.in_flight_updates[rand::rng().random_range(0..self.in_flight_updates.len())];
+
+        Some(IsolationProbe {
+            state_reader: self.state_reader.clone(),
+            behav: self.behav,
+            verbose: self.verbose,
+            update_worker_id: *update_worker_id,
+            in_flight: in_flight.clone(),
+        })
+    }
+
+    /// A probe for a random conserved invariant, if there are any
+    fn conservation_probe(&self) -> Option<ConservationProbe> {
+        let invariant = self.conserved.choose(&mut rand::rng())?;
+
+        Some(ConservationProbe {
+            state_reader: self.state_reader.clone(),
+            behav: self.behav,
+            verbose: self.verbose,
+            invariant: invariant.clone(),
+        })
+    }
+
+    /// Sends the next query as soon as the previous one was answered
+    async fn run_closed_loop(&mut self, measurements: &mut ReadMeasurements) -> Result<(), WorkerError> {
+        loop {
+            let query = self.query_gen.next_query();
+
+            let outcome = send_query(&self.client, &self.endpoint, self.behav, Instant::now(), &query).await?;
+
+            if let (QueryOutcome::Unavailable, Some(gate)) = (&outcome, &self.restart_gate) {
+                gate.wait().await;
+                continue;
+            }
+
+            measurements.record(&query, outcome);
+
+            if let Some(probe) = self.isolation_probe() {
+                probe.run().await?;
+            }
+
+            if let Some(probe) = self.conservation_probe() {
+                probe.run().await?;
+            }
         }
     }
 
-    pub async fn execute(
+    /// Sends queries according to the target rate, regardless of whether the previous ones were answered.
+    ///
+    /// Latencies are measured from the time a query was scheduled to be sent, so that a server that cannot
+    /// keep up with the rate shows up as queueing delay instead of being hidden by the reader slowing down.
+    /// Queries that are still unanswered when the reader is stopped are recorded as unfinished.
+    ///
+    /// If the server cannot be reached, no queries are scheduled until the reader passed the `restart_gate`,
+    /// and the schedule resumes from then on instead of catching up on the missed queries.
+    /// At most one isolation and conservation probe runs at a time, so that probes do not add unbounded load.
+    async fn run_open_loop(
         &mut self,
-        mut stop: tokio::sync::broadcast::Receiver<()>,
-    ) -> Result<BTreeMap<usize, Qps>, WorkerError> {
-        let mut query_timings: BTreeMap<_, Vec<Duration>> = Default::default();
+        rate: TargetRate,
+        measurements: &mut ReadMeasurements,
+        stop: &mut tokio::sync::broadcast::Receiver<()>,
+    ) -> Result<(), WorkerError> {
+        let mut outstanding = JoinSet::new();
+        let mut scheduled_starts = HashMap::new();
+        let mut probes = JoinSet::new();
+        let start = Instant::now();
+        let mut next_start = start;
+
+        let record_unfinished =
+            |measurements: &mut ReadMeasurements, scheduled_starts: &HashMap<task::Id, (Arc<ReadQuery>, Instant)>| {
+                for (query, scheduled_start) in scheduled_starts.values() {
+                    measurements.record_unfinished(query, scheduled_start.elapsed());
+                }
 
-        let worker = async {
-            loop {
-                let (qid, q) = self.query_gen.next_query();
+                measurements.open_loop_duration = Some(start.elapsed());
+            };
 
-                let qfut = self.client.get(self.endpoint.clone()).query(&[("query", &q)]).send();
-                let dur = Self::measure_query(self.behav, qfut)
-                    .await
-                    .map_err(|e| WorkerError::ReadFailed { query: q.into_owned(), err: e })?;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(next_start.into()) => {
+                    let query = self.query_gen.next_query();
+                    let (client, endpoint, behav) = (self.client.clone(), self.endpoint.clone(), self.behav);
+                    let scheduled_start = next_start;
 
-                if let (Some(id), Some(dur)) = (qid, dur) {
-                    query_timings.entry(id).or_default().push(dur);
-                }
+                    let task = outstanding.spawn({
+                        let query = query.clone();
+                        async move { send_query(&client, &endpoint, behav, scheduled_start, &query).await }
+                    });
+                    scheduled_starts.insert(task.id(), (query, scheduled_start));
+
+                    next_start += rate.next_interval();
+                },
+                Some(res) = outstanding.join_next_with_id() => {
+                    let (id, outcome) = res.expect("query task panicked");
+                    let (query, _) = scheduled_starts.remove(&id).unwrap();
+                    let outcome = outcome?;
+
+                    if let (QueryOutcome::Unavailable, Some(gate)) = (&outcome, &self.restart_gate) {
+                        tokio::select! {
+                            _ = gate.wait() => next_start = next_start.max(Instant::now()),
+                            _ = stop.recv() => {
+                                record_unfinished(measurements, &scheduled_starts);
+                                return Ok(());
+                            },
+                        }
+
+                        continue;
+                    }
+
+                    measurements.record(&query, outcome);
+
+                    if !probes.is_empty() {
+                        continue;
+                    }
+
+                    let probe = self.isolation_probe();
+                    let conservation_probe = self.conservation_probe();
+
+                    probes.spawn(async move {
+                        if let Some(probe) = probe {
+                            probe.run().await?;
+                        }
+
+                        if let Some(probe) = conservation_probe {
+                            probe.run().await?;
+                        }
+
+                        Ok(())
+                    });
+                },
+                Some(res) = probes.join_next() => res.expect("probe task panicked")?,
+                _ = stop.recv() => {
+                    record_unfinished(measurements, &scheduled_starts);
+                    return Ok(());
+                },
             }
-        };
+        }
+    }
+
+    pub async fn execute(
+        &mut self,
+        mut stop: tokio::sync::broadcast::Receiver<()>,
+    ) -> Result<ReadMeasurements, WorkerError> {
+        let mut measurements = ReadMeasurements::default();
 
-        let success = tokio::select! {
-            res = worker => res,
-            _ = stop.recv() => Ok(())
+        let success = match self.target_rate {
+            None => tokio::select! {
+                res = self.run_closed_loop(&mut measurements) => res,
+                _ = stop.recv() => Ok(())
+            },
+            Some(rate) => self.run_open_loop(rate, &mut measurements, &mut stop).await,
         };
 
         success?;
 
-        Ok(query_timings
-            .into_iter()
-            .map(|(qid, durations)| {
-                let avg_duration_secs = durations.iter().sum::<Duration>().as_secs_f64() / durations.len() as f64;
-                let qps = 1.0 / avg_duration_secs;
-
-                (qid, qps)
-            })
-            .collect())
+        Ok(measurements)
     }
 }
@@ -0,0 +1,108 @@
+use crate::{
+    db_state::{DbState, StateForm},
+    error::WorkerError,
+    operation::StateSource,
+    update_worker::StateReader,
+    Query, WorkerBehaviour,
+};
+use reqwest::{Client, Response, Url};
+use std::{
+    ops::ControlFlow,
+    time::{Duration, Instant},
+};
+
+/// What is polled to find out whether the server is ready to answer requests
+#[derive(Clone)]
+pub enum ReadinessCheck {
+    /// An ASK query against the query endpoint, which must return true
+    Ask(Query),
+    /// A GET request to a health URL, which must be answered with a 2xx status
+    Url(Url),
+}
+
+/// Waits for the server to become ready after it was started or restarted
+#[derive(Clone)]
+pub struct ReadinessProbe {
+    client: Client,
+    state_reader: StateReader,
+    check: ReadinessCheck,
+    /// Timeout of a single probe
+    timeout: Duration,
+    poll_interval: Duration,
+    /// How long the server may take to become ready
+    deadline: Duration,
+}
+
+impl ReadinessProbe {
+    pub fn new(
+        query_endpoint: Url,
+        check: ReadinessCheck,
+        timeout: Duration,
+        poll_interval: Duration,
+        deadline: Duration,
+    ) -> anyhow::Result<Self> {
+        if let ReadinessCheck::Ask(query) = &check {
+            anyhow::ensure!(
+                matches!(StateForm::of_query(query), Ok(StateForm::Boolean)),
+                "The readiness query must be an ASK query"
+            );
+        }
+
+        let client = Client::builder().timeout(timeout).build()?;
+
+        Ok(Self {
+            state_reader: StateReader::new(client.clone(), query_endpoint, None, None),
+            client,
+            check,
+            timeout,
+            poll_interval,
+            deadline,
+        })
+    }
+
+    /// Probes the server every poll interval until it is ready. Returns how long that took.
+    pub async fn wait_until_ready(&self) -> Result<Duration, WorkerError> {
+        let start = Instant::now();
+
+        loop {
+            let err = match tokio::time::timeout(self.timeout, self.probe()).await {
+                Ok(Ok(())) => return Ok(start.elapsed()),
+                Ok(Err(err)) => err,
+                Err(_) => format!("No response within {}ms", self.timeout.as_millis()),
+            };
+
+            if start.elapsed() + self.poll_interval > self.deadline {
+                return Err(WorkerError::ServerNotReady { deadline_s: self.deadline.as_secs_f64(), err });
+            }
+
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    async fn probe(&self) -> Result<(), String> {
+        match &self.check {
+            ReadinessCheck::Ask(query) => {
+                let source = StateSource::Query(query.clone());
+
+                match self
+                    .state_reader
+                    .fetch(&source, StateForm::Boolean, WorkerBehaviour::ReportConnectionError)
+                    .await
+                {
+                    Ok(ControlFlow::Break(DbState::Boolean(true))) => Ok(()),
+                    Ok(ControlFlow::Break(state)) => Err(format!("The readiness query returned {state}")),
+                    Ok(ControlFlow::Continue(())) => unreachable!("connection errors are reported"),
+                    Err(e) => Err(format!("{:#}", anyhow::Error::from(e))),
+                }
+            },
+            ReadinessCheck::Url(url) => self
+                .client
+                .get(url.clone())
+                .send()
+                .await
+                .and_then(Response::error_for_status)
+                .map(|_| ())
+                .map_err(|e| format!("{:#}", anyhow::Error::from(e))),
+        }
+    }
+}
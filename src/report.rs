@@ -0,0 +1,157 @@
+use crate::{
+    error::WorkerError,
+    history::LinearizabilityReport,
+    kill_worker::{KillCycle, KillSchedule},
+    latency::LatencyPercentiles,
+    random_read_worker::{ArrivalSchedule, QueryId},
+    server::KillSignal,
+    AvgQps, Qps,
+};
+use anyhow::Context;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The configuration a test was run with
+#[derive(Serialize)]
+pub struct RunConfig {
+    pub subcommand: &'static str,
+    pub query_endpoint: String,
+    pub update_endpoint: Option<String>,
+    pub graph_store_endpoint: Option<String>,
+    pub num_update_workers: usize,
+    pub update_query_dir: Option<PathBuf>,
+    pub num_random_read_workers: usize,
+    pub random_read_workers_query_file: Option<PathBuf>,
+    pub query_mix: Option<PathBuf>,
+    pub query_template: Option<PathBuf>,
+    pub target_qps: Option<f64>,
+    pub arrival_schedule: Option<ArrivalSchedule>,
+    pub duration_s: Option<u64>,
+    pub kill_delay_s: Option<u64>,
+    pub kill_schedule: Option<KillSchedule>,
+    /// The signal the server was killed with, if the test supervised the server process itself
+    pub kill_signal: Option<KillSignal>,
+    /// Whether the unsynced data of the server was dropped on every kill, to simulate power loss
+    pub power_cut: bool,
+    pub check_linearizability: bool,
+}
+
+#[derive(Serialize)]
+pub struct UpdateWorkerReport {
+    pub worker_id: usize,
+    /// The directory of the worker's operations in the workload, e.g. `worker_0` for the worker with id 1
+    pub workload_dir: String,
+    /// Time from test start until the worker finished
+    pub finished_after_s: f64,
+    pub num_operations: usize,
+    /// Durations of the successfully completed operations, in order
+    pub operation_durations_s: Vec<f64>,
+    pub error: Option<WorkerError>,
+    /// The message of the error including its verbose info, for the JUnit file
+    #[serde(skip)]
+    pub error_details: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ReaderReport {
+    pub worker_id: usize,
+    /// For open-loop readers, the number of answered queries per second
+    pub avg_qps: Option<AvgQps>,
+    pub per_query_qps: BTreeMap<QueryId, Qps>,
+    /// Latency percentiles over all queries of this reader
+    pub latency: Option<LatencyPercentiles>,
+    pub per_query_latency: BTreeMap<QueryId, LatencyPercentiles>,
+    /// Number of timed out queries per query name, only of the queries that timed out at least once
+    pub per_query_timeouts: BTreeMap<QueryId, u64>,
+    /// Number of timed out queries over all queries
+    pub timeouts: u64,
+    /// Number of queries that were still unanswered when the reader was stopped
+    pub unfinished_queries: u64,
+    pub error: Option<WorkerError>,
+    /// The message of the error including its verbose info, for the JUnit file
+    #[serde(skip)]
+    pub error_details: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct KillWorkerReport {
+    pub n_kills: usize,
+    /// Replays the kill schedule when passed as `--seed`
+    pub seed: u64,
+    pub cycles: Vec<KillCycle>,
+    pub error: Option<WorkerError>,
+    /// The message of the error including its verbose info, for the JUnit file
+    #[serde(skip)]
+    pub error_details: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct InvariantReport {
+    pub n_checks: usize,
+    pub error: Option<WorkerError>,
+}
+
+/// Machine-readable summary of a `stress` or `verify` run
+#[derive(Serialize)]
+pub struct Report {
+    pub config: RunConfig,
+    pub passed: bool,
+    pub failure: Option<String>,
+    pub start_time_unix_s: Option<f64>,
+    pub end_time_unix_s: Option<f64>,
+    pub update_workers: Vec<UpdateWorkerReport>,
+    pub readers: Vec<ReaderReport>,
+    /// Latency percentiles over all queries of all readers
+    pub latency: Option<LatencyPercentiles>,
+    pub kill_worker: Option<KillWorkerReport>,
+    /// Only present if the workload defines invariants
+    pub invariants: Option<InvariantReport>,
+    /// Only present if the history was checked for linearizability
+    pub linearizability: Option<LinearizabilityReport>,
+}
+
+pub fn unix_time_s() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+impl Report {
+    pub fn new(config: RunConfig) -> Self {
+        Self {
+            config,
+            passed: false,
+            failure: None,
+            start_time_unix_s: None,
+            end_time_unix_s: None,
+            update_workers: Vec::new(),
+            readers: Vec::new(),
+            latency: None,
+            kill_worker: None,
+            invariants: None,
+            linearizability: None,
+        }
+    }
+
+    /// Marks the point in time the workers were started
+    pub fn started(&mut self) {
+        self.start_time_unix_s = Some(unix_time_s());
+    }
+
+    pub fn finish(&mut self, result: &anyhow::Result<()>) {
+        self.end_time_unix_s = Some(unix_time_s());
+        self.passed = result.is_ok();
+        self.failure = result.as_ref().err().map(ToString::to_string);
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let file = File::create(path).context("Unable to create report file")?;
+        serde_json::to_writer_pretty(file, self).context("Unable to write report")
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fs::File,
+    io,
+    path::PathBuf,
+    process::Stdio,
+    time::Duration,
+};
+use tokio::process::{Child, Command};
+
+/// How long a killed server may take to exit before it is killed with SIGKILL
+const KILL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often it is checked whether all processes of a killed server exited
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The signal a supervised server is killed with
+#[derive(Debug, Clone, Copy, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KillSignal {
+    /// SIGKILL, the server cannot react to it
+    Kill,
+    /// SIGTERM, i.e. a regular shutdown
+    Term,
+    /// SIGINT, like pressing Ctrl+C
+    Int,
+    /// SIGABRT, like a failed assertion
+    Abrt,
+    /// SIGSEGV, like a crash
+    Segv,
+}
+
+impl KillSignal {
+    fn as_raw(self) -> libc::c_int {
+        match self {
+            KillSignal::Kill => libc::SIGKILL,
+            KillSignal::Term => libc::SIGTERM,
+            KillSignal::Int => libc::SIGINT,
+            KillSignal::Abrt => libc::SIGABRT,
+            KillSignal::Segv => libc::SIGSEGV,
+        }
+    }
+}
+
+/// How the server under test is started, killed and restarted in durability mode
+pub enum ServerControl {
+    /// Via user-provided shell scripts
+    Scripts {
+        start: OsString,
+        kill: OsString,
+        restart: OsString,
+    },
+    /// The server runs as a child process of the test
+    Process(ServerProcess),
+}
+
+impl ServerControl {
+    pub async fn start(&mut self) -> io::Result<()> {
+        match self {
+            ServerControl::Scripts { start, .. } => run_script(start).await,
+            ServerControl::Process(process) => process.spawn(),
+        }
+    }
+
+    pub async fn kill(&mut self) -> io::Result<()> {
+        match self {
+            ServerControl::Scripts { kill, .. } => run_script(kill).await,
+            ServerControl::Process(process) => process.kill().await,
+        }
+    }
+
+    pub async fn restart(&mut self) -> io::Result<()> {
+        match self {
+            ServerControl::Scripts { restart, .. } => run_script(restart).await,
+            ServerControl::Process(process) => process.spawn(),
+        }
+    }
+
+    /// Stops the server at the end of the test
+    pub async fn shut_down(&mut self) {
+        match self {
+            ServerControl::Scripts { kill, .. } => {
+                let _ = Command::new("sh").arg("-c").arg(kill).spawn();
+            },
+            // the kill worker may have been stopped between a kill and the following restart
+            ServerControl::Process(process) if process.running.is_none() => (),
+            ServerControl::Process(process) => {
+                if let Err(e) = process.kill().await {
+                    tracing::warn!("Unable to shut down server. Error: {e}");
+                }
+            },
+        }
+    }
+}
+
+async fn run_script(script: &OsStr) -> io::Result<()> {
+    let status = Command::new("sh").arg("-c").arg(script).status().await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other("Exit code != 0"))
+    }
+}
+
+/// A server that is started by running a shell command as a child process.
+///
+/// The command runs in its own process group, which receives the kill signal, so that processes the command
+/// spawned are killed too. The output of every lifecycle (from a start until the following kill) is written to
+/// `server_N.stdout.log` and `server_N.stderr.log` in the log directory, where N is 0 for the initial start
+/// and counts the restarts afterwards.
+///
+/// A kill only completes once every process of the group exited, so that a restarted server does not race the
+/// previous one for its port or data directory. If the processes do not exit within [`KILL_TIMEOUT`], they are
+/// killed with SIGKILL.
+pub struct ServerProcess {
+    command: OsString,
+    signal: KillSignal,
+    log_dir: PathBuf,
+    n_lifecycles: usize,
+    /// The current lifecycle, until all of its processes exited
+    running: Option<RunningServer>,
+}
+
+struct RunningServer {
+    /// The shell running the server command
+    child: Child,
+    /// The id of the process group of the server, which equals the pid of the shell
+    pgid: u32,
+}
+
+impl ServerProcess {
+    pub fn new(command: OsString, signal: KillSignal, log_dir: PathBuf) -> Self {
+        Self { command, signal, log_dir, n_lifecycles: 0, running: None }
+    }
+
+    fn spawn(&mut self) -> io::Result<()> {
+        if self.running.is_some() {
+            return Err(io::Error::other("The server is already running"));
+        }
+
+        std::fs::create_dir_all(&self.log_dir)?;
+
+        let log_file = |stream: &str| {
+            let path = self.log_dir.join(format!("server_{}.{stream}.log", self.n_lifecycles));
+            File::create(&path).map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+        };
+
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::null())
+            .stdout(log_file("stdout")?)
+            .stderr(log_file("stderr")?)
+            .process_group(0)
+            .spawn()?;
+        let pgid = child
+            .id()
+            .ok_or_else(|| io::Error::other("The server exited immediately"))?;
+
+        tracing::info!(
+            "Started server (pid {pgid}), logging to {}",
+            self.log_dir
+                .join(format!("server_{}.*.log", self.n_lifecycles))
+                .display()
+        );
+
+        self.running = Some(RunningServer { child, pgid });
+        self.n_lifecycles += 1;
+        Ok(())
+    }
+
+    async fn kill(&mut self) -> io::Result<()> {
+        // the server stays running until it exited, so that it is still killed if this future is dropped
+        let Some(RunningServer { child, pgid }) = &mut self.running else {
+            return Err(io::Error::other("The server is not running"));
+        };
+        let pgid = *pgid;
+
+        if let Some(status) = child.try_wait()? {
+            self.running = None;
+            return Err(io::Error::other(format!(
+                "The server exited before it was killed ({status})"
+            )));
+        }
+
+        signal_process_group(pgid, self.signal)?;
+
+        if tokio::time::timeout(KILL_TIMEOUT, wait_for_exit(child, pgid))
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                "The server did not exit within {}s after it was killed, killing it with SIGKILL",
+                KILL_TIMEOUT.as_secs()
+            );
+
+            signal_process_group(pgid, KillSignal::Kill)?;
+            tokio::time::timeout(KILL_TIMEOUT, wait_for_exit(child, pgid))
+                .await
+                .map_err(|_| io::Error::other("The server did not exit after it was killed with SIGKILL"))??;
+        }
+
+        self.running = None;
+        Ok(())
+    }
+}
+
+impl Drop for ServerProcess {
+    /// Makes sure the server does not outlive the test if it ends early
+    fn drop(&mut self) {
+        // the shell may have exited already while other processes of the group are still running
+        if let Some(RunningServer { pgid, .. }) = &self.running {
+            let _ = signal_process_group(*pgid, KillSignal::Kill);
+        }
+    }
+}
+
+/// Waits until the shell running the server command and all other processes of its group exited
+async fn wait_for_exit(child: &mut Child, pgid: u32) -> io::Result<()> {
+    child.wait().await?;
+
+    // unless the command `exec`s the server, the server may still be shutting down after the shell exited
+    while process_group_exists(pgid)? {
+        tokio::time::sleep(EXIT_POLL_INTERVAL).await;
+    }
+
+    Ok(())
+}
+
+fn signal_process_group(pgid: u32, signal: KillSignal) -> io::Result<()> {
+    // SAFETY: kill only takes plain integers and has no memory safety preconditions
+    if unsafe { libc::kill(-(pgid as libc::pid_t), signal.as_raw()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn process_group_exists(pgid: u32) -> io::Result<bool> {
+    // SAFETY: see signal_process_group, signal 0 only checks whether the group exists
+    if unsafe { libc::kill(-(pgid as libc::pid_t), 0) } == 0 {
+        return Ok(true);
+    }
+
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::ESRCH) => Ok(false),
+        // the group exists, but contains processes the test may not signal
+        Some(libc::EPERM) => Ok(true),
+        _ => Err(err),
+    }
+}
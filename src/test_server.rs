@@ -0,0 +1,84 @@
+//! A minimal HTTP server for the tests of the workers, answering every request from a closure
+
+use reqwest::Url;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// A response of the [`serve`]d server
+pub struct StubResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+/// Serves on a free local port until the test ends, answering every request by `respond`,
+/// which gets the request line (e.g. `GET /?query=... HTTP/1.1`)
+pub async fn serve(respond: impl Fn(&str) -> StubResponse + Send + Sync + 'static) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let Some(request_line) = read_request(&mut stream).await else {
+                continue;
+            };
+
+            let StubResponse { status, content_type, body } = respond(&request_line);
+            let response = format!(
+                "HTTP/1.1 {status} Stub\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{body}",
+                body.len()
+            );
+
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+
+    url
+}
+
+/// A URL nothing listens on, so that requests fail with a connection error
+pub async fn unreachable_url() -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap()
+}
+
+/// Reads the head and body of a request, returns its request line
+async fn read_request(stream: &mut TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buf = [0; 4096];
+
+    let head_len = loop {
+        let n = stream.read(&mut buf).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+
+        request.extend_from_slice(&buf[..n]);
+
+        if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&request[..head_len]).into_owned();
+    let content_length: usize = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0);
+
+    while request.len() < head_len + content_length {
+        let n = stream.read(&mut buf).await.ok()?;
+        if n == 0 {
+            break;
+        }
+
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    head.lines().next().map(ToOwned::to_owned)
+}
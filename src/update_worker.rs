@@ -1,86 +1,265 @@
 use crate::{
-    error::{InvalidStateVerboseInfo, UpdateFailedVerboseInfo, WorkerError},
-    Query, WorkerBehaviour,
+    db_state::{DbState, StateForm},
+    durability::WorkerDurabilityHandle,
+    error::{
+        InvalidStateVerboseInfo, ReadStateError, RollbackVerboseInfo, TransitionVerboseInfo, UpdateError,
+        UpdateFailedVerboseInfo, WorkerError,
+    },
+    history::{EventKind, HistoryRecorder},
+    invariant::{InvariantTracker, INVARIANTS_FILE},
+    operation::{Endpoint, Method, Request, Requests, StateSource, UpdateOperation, Validate},
+    WorkerBehaviour,
 };
 use anyhow::Context;
-use reqwest::{header, Client, Url};
-use serde::Deserialize;
-use std::{collections::HashMap, fs::File, io, ops::ControlFlow, path::Path};
+use oxrdf::{Graph, NamedNode};
+use reqwest::{header, Client, RequestBuilder, Response, StatusCode, Url};
+use std::{
+    fs::File,
+    io,
+    ops::ControlFlow,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{watch, RwLockReadGuard};
 
-type DbState = String;
+/// Reads the states of validations, either from the query endpoint or from the graph store endpoint
+#[derive(Clone)]
+pub struct StateReader {
+    client: Client,
+    query_endpoint: Url,
+    /// Only known when verifying
+    graph_store_endpoint: Option<Url>,
+    /// Records every state that was read, if the history is checked
+    history: Option<HistoryRecorder>,
+}
 
-fn normalize_dbstate(state: DbState) -> DbState {
-    let mut lines: Vec<&str> = state.lines().map(|line| line.trim()).collect();
+impl StateReader {
+    pub fn new(
+        client: Client,
+        query_endpoint: Url,
+        graph_store_endpoint: Option<Url>,
+        history: Option<HistoryRecorder>,
+    ) -> Self {
+        Self { client, query_endpoint, graph_store_endpoint, history }
+    }
 
-    lines.sort();
+    /// Fetches the state described by `source`, which is expected to be of the given form
+    pub async fn fetch(
+        &self,
+        source: &StateSource,
+        form: StateForm,
+        behav: WorkerBehaviour,
+    ) -> Result<ControlFlow<DbState>, ReadStateError> {
+        let Some(history) = &self.history else {
+            return self.fetch_state(source, form, behav).await;
+        };
 
-    lines.into_iter().flat_map(|line| [line, "\n"]).collect()
-}
+        let invoked = history.now();
+        let res = self.fetch_state(source, form, behav).await;
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
-enum Endpoint {
-    Update,
-    Gsp,
-}
+        if let Ok(ControlFlow::Break(state)) = &res {
+            history.record(EventKind::Read, source, state, invoked, Some(history.now()));
+        }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
-enum Method {
-    Post,
-    Put,
-    Delete,
-}
+        res
+    }
 
-#[derive(Debug, Deserialize)]
-struct Validate {
-    query: Query,
-    expected: DbState,
-}
+    async fn fetch_state(
+        &self,
+        source: &StateSource,
+        form: StateForm,
+        behav: WorkerBehaviour,
+    ) -> Result<ControlFlow<DbState>, ReadStateError> {
+        match source {
+            StateSource::Query(query) | StateSource::DatasetQuery(query) => {
+                let req = self
+                    .client
+                    .get(self.query_endpoint.clone())
+                    .header(header::ACCEPT, form.accept())
+                    .query(&[("query", query)]);
 
-#[derive(Debug, Deserialize)]
-struct UpdateOperation {
-    endpoint: Endpoint,
-    query_params: HashMap<String, String>,
-    headers: HashMap<String, String>,
-    method: Method,
-    body: String,
-    validate: Validate,
-}
+                match Self::send(req, behav).await? {
+                    ControlFlow::Break(resp) => Self::read_state(resp, form, behav).await,
+                    ControlFlow::Continue(()) => Ok(ControlFlow::Continue(())),
+                }
+            },
+            StateSource::Graphs(graphs) => {
+                let mut quads = Vec::new();
+
+                for graph in graphs {
+                    // a graph that does not exist is read as an empty graph
+                    let triples = match self.fetch_graph(graph, false, behav).await? {
+                        ControlFlow::Break(triples) => triples.unwrap_or_default(),
+                        ControlFlow::Continue(()) => return Ok(ControlFlow::Continue(())),
+                    };
+
+                    let graph_name = NamedNode::new_unchecked(graph);
+                    quads.extend(
+                        triples
+                            .iter()
+                            .map(|triple| triple.into_owned().in_graph(graph_name.clone())),
+                    );
+                }
 
-impl UpdateOperation {
-    fn normalize(self) -> Self {
-        Self {
-            validate: Validate {
-                query: self.validate.query,
-                expected: normalize_dbstate(self.validate.expected),
+                Ok(ControlFlow::Break(quads.into_iter().collect()))
+            },
+            StateSource::GspGraph(graph) => match self.fetch_graph(graph, true, behav).await? {
+                ControlFlow::Break(Some(triples)) => Ok(ControlFlow::Break(DbState::Graph(triples))),
+                ControlFlow::Break(None) => Ok(ControlFlow::Break(DbState::Absent)),
+                ControlFlow::Continue(()) => Ok(ControlFlow::Continue(())),
             },
-            ..self
+        }
+    }
+
+    /// Reads a named graph from the graph store endpoint by a GET request. Returns `None` if the graph does not
+    /// exist (404). With `check_existence`, the existence is checked by a HEAD request first, unless the graph store
+    /// does not support HEAD requests (405 or 501).
+    async fn fetch_graph(
+        &self,
+        graph: &str,
+        check_existence: bool,
+        behav: WorkerBehaviour,
+    ) -> Result<ControlFlow<Option<Graph>>, ReadStateError> {
+        let graph_store_endpoint = self
+            .graph_store_endpoint
+            .as_ref()
+            .expect("the graph store endpoint is known when verifying");
+
+        if check_existence {
+            let head = self
+                .client
+                .head(graph_store_endpoint.clone())
+                .query(&[("graph", graph)]);
+
+            match Self::send(head, behav).await? {
+                ControlFlow::Break(resp) if resp.status() == StatusCode::NOT_FOUND => {
+                    return Ok(ControlFlow::Break(None))
+                },
+                ControlFlow::Break(resp)
+                    if matches!(
+                        resp.status(),
+                        StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+                    ) => {},
+                ControlFlow::Break(resp) => {
+                    resp.error_for_status()?;
+                },
+                ControlFlow::Continue(()) => return Ok(ControlFlow::Continue(())),
+            }
+        }
+
+        let get = self
+            .client
+            .get(graph_store_endpoint.clone())
+            .header(header::ACCEPT, StateForm::Graph.accept())
+            .query(&[("graph", graph)]);
+
+        // the graph may also have been deleted concurrently since the HEAD request
+        let resp = match Self::send(get, behav).await? {
+            ControlFlow::Break(resp) if resp.status() == StatusCode::NOT_FOUND => return Ok(ControlFlow::Break(None)),
+            ControlFlow::Break(resp) => resp,
+            ControlFlow::Continue(()) => return Ok(ControlFlow::Continue(())),
+        };
+
+        match Self::read_state(resp, StateForm::Graph, behav).await? {
+            ControlFlow::Break(DbState::Graph(triples)) => Ok(ControlFlow::Break(Some(triples))),
+            ControlFlow::Break(_) => unreachable!("graphs are parsed as graphs"),
+            ControlFlow::Continue(()) => Ok(ControlFlow::Continue(())),
+        }
+    }
+
+    async fn send(req: RequestBuilder, behav: WorkerBehaviour) -> Result<ControlFlow<Response>, ReadStateError> {
+        match req.send().await {
+            Ok(resp) => Ok(ControlFlow::Break(resp)),
+            Err(_) if behav == WorkerBehaviour::IgnoreConnectionError => Ok(ControlFlow::Continue(())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn read_state(
+        resp: Response,
+        form: StateForm,
+        behav: WorkerBehaviour,
+    ) -> Result<ControlFlow<DbState>, ReadStateError> {
+        let resp = resp.error_for_status()?;
+        let content_type = resp
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(ToOwned::to_owned);
+
+        match resp.bytes().await {
+            Ok(body) => Ok(ControlFlow::Break(DbState::parse_response(
+                &body,
+                content_type.as_deref(),
+                form,
+            )?)),
+            Err(_) if behav == WorkerBehaviour::IgnoreConnectionError => Ok(ControlFlow::Continue(())),
+            Err(e) => Err(e.into()),
         }
     }
 }
 
+/// The operation an update worker is currently applying, as published to concurrent readers.
+///
+/// While an operation is published, its validation query must return either `pre_state`, `post_state`
+/// or (for non-atomic multi-step operations) one of the `intermediate_states`.
+#[derive(Debug)]
+pub struct InFlightOperation {
+    pub update_id: usize,
+    pub source: StateSource,
+    pub pre_state: DbState,
+    pub post_state: DbState,
+    pub intermediate_states: Vec<DbState>,
+}
+
+pub type InFlightReceiver = watch::Receiver<Option<Arc<InFlightOperation>>>;
+
+/// The configuration shared by all update workers of a test
+#[derive(Clone)]
+pub struct UpdateWorkerConfig {
+    pub query_endpoint: Url,
+    pub update_endpoint: Url,
+    pub graph_store_endpoint: Url,
+    pub verbose: bool,
+    pub behav: WorkerBehaviour,
+    pub invariants: InvariantTracker,
+}
+
 pub struct UpdateWorker {
-    query_endpoint: Url,
+    state_reader: StateReader,
     update_endpoint: Url,
     graph_store_endpoint: Url,
     client: Client,
     queries: Vec<UpdateOperation>,
     verbose: bool,
     behav: WorkerBehaviour,
+    in_flight: watch::Sender<Option<Arc<InFlightOperation>>>,
+    durability: Option<WorkerDurabilityHandle>,
+    history: Option<HistoryRecorder>,
+    invariants: InvariantTracker,
+    op_durations: Vec<Duration>,
 }
 
 impl UpdateWorker {
     pub fn new(
         base_dir: &Path,
-        query_endpoint: Url,
-        update_endpoint: Url,
-        graph_store_endpoint: Url,
-        verbose: bool,
-        behav: WorkerBehaviour,
+        UpdateWorkerConfig {
+            query_endpoint,
+            update_endpoint,
+            graph_store_endpoint,
+            verbose,
+            behav,
+            invariants,
+        }: UpdateWorkerConfig,
+        durability: Option<WorkerDurabilityHandle>,
+        history: Option<HistoryRecorder>,
     ) -> anyhow::Result<Self> {
         let mut queries = Vec::new();
 
+        // the expected state of the last validated step, i.e. the state before the next step if it reads the same data
+        let mut last_state: Option<(StateSource, DbState)> = None;
+
         for op in 0.. {
             let file = match File::open(base_dir.join(format!("op_{op}.json"))) {
                 Ok(file) => file,
@@ -91,7 +270,57 @@ impl UpdateWorker {
             let update: UpdateOperation =
                 serde_json::from_reader(file).context(format!("Unable to deserialize operation {op}"))?;
 
-            queries.push(update.normalize());
+            if let Requests::Steps { steps, .. } = &update.requests {
+                let Some((last, intermediate)) = steps.split_last() else {
+                    anyhow::bail!("Operation {op} does not have any steps");
+                };
+
+                anyhow::ensure!(
+                    last.validate.is_none(),
+                    "The last step of operation {op} must not have a validate block, \
+                     it is validated by the validate block of the operation"
+                );
+
+                // the outcome of a step that was interrupted by a server kill can only be resolved using its validation
+                anyhow::ensure!(
+                    behav == WorkerBehaviour::ReportConnectionError
+                        || intermediate.iter().all(|s| s.validate.is_some()),
+                    "Every step of operation {op} except the last one needs a validate block in durability mode"
+                );
+            }
+
+            for (step, (request, validate)) in update.steps().into_iter().enumerate() {
+                let Some(validate) = validate else {
+                    last_state = None;
+                    continue;
+                };
+
+                if let Some((_, pre_state)) = last_state.as_ref().filter(|(source, _)| *source == validate.source) {
+                    anyhow::ensure!(
+                        request.expect.is_success() || validate.expected == *pre_state,
+                        "Step {step} of operation {op} is expected to be rejected, \
+                         so its validate block must expect the state before the step"
+                    );
+                }
+
+                last_state = Some((validate.source.clone(), validate.expected.clone()));
+            }
+
+            for (name, delta) in &update.invariant_deltas {
+                match invariants.get(name) {
+                    None => {
+                        anyhow::bail!(
+                            "Operation {op} refers to invariant {name}, which is not defined in {INVARIANTS_FILE}"
+                        )
+                    },
+                    Some(invariant) if invariant.conserved && *delta != 0 => {
+                        anyhow::bail!("Operation {op} changes invariant {name}, which is conserved")
+                    },
+                    Some(_) => (),
+                }
+            }
+
+            queries.push(update);
         }
 
         anyhow::ensure!(
@@ -100,114 +329,496 @@ impl UpdateWorker {
             base_dir.display()
         );
 
+        let client = Client::new();
+
         Ok(Self {
-            query_endpoint,
+            state_reader: StateReader::new(
+                client.clone(),
+                query_endpoint,
+                Some(graph_store_endpoint.clone()),
+                history.clone(),
+            ),
             update_endpoint,
             graph_store_endpoint,
-            client: Client::new(),
+            client,
             queries,
             verbose,
             behav,
+            in_flight: watch::Sender::new(None),
+            durability,
+            history,
+            invariants,
+            op_durations: Vec::new(),
         })
     }
-    async fn read_current_state(
-        &self,
-        UpdateOperation { validate, .. }: &UpdateOperation,
-    ) -> reqwest::Result<ControlFlow<DbState>> {
-        let resp = self
-            .client
-            .get(self.query_endpoint.clone())
-            .header(header::ACCEPT, "application/n-triples")
-            .query(&[("query", validate.query.clone())])
-            .send()
-            .await;
 
-        match resp {
-            Ok(resp) => {
-                let resp = resp.error_for_status()?;
-                match resp.text().await {
-                    Ok(state) => Ok(ControlFlow::Break(normalize_dbstate(state))),
-                    Err(_) if self.behav == WorkerBehaviour::IgnoreConnectionError => Ok(ControlFlow::Continue(())),
-                    Err(e) => Err(e),
-                }
-            },
-            Err(_) if self.behav == WorkerBehaviour::IgnoreConnectionError => Ok(ControlFlow::Continue(())),
-            Err(e) => Err(e),
+    pub fn num_operations(&self) -> usize {
+        self.queries.len()
+    }
+
+    /// The durations of all operations that were completed successfully so far, in order
+    pub fn op_durations(&self) -> &[Duration] {
+        &self.op_durations
+    }
+
+    /// Subscribe to the operation this worker is currently applying.
+    /// Used by readers to check that they never observe a partially applied update.
+    pub fn subscribe_in_flight(&self) -> InFlightReceiver {
+        self.in_flight.subscribe()
+    }
+
+    /// In durability mode requests must not be issued while the server is being restarted and checked
+    async fn request_permit(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        match &self.durability {
+            Some(durability) => Some(durability.request_permit().await),
+            None => None,
         }
     }
 
-    async fn issue_update(&self, operation: &UpdateOperation) -> reqwest::Result<ControlFlow<()>> {
-        let endpoint = match operation.endpoint {
+    async fn read_current_state(&self, validate: &Validate) -> Result<ControlFlow<DbState>, ReadStateError> {
+        let _permit = self.request_permit().await;
+        self.state_reader
+            .fetch(&validate.source, validate.expected.form(), self.behav)
+            .await
+    }
+
+    /// Returns `Continue` with the connection error if connection errors are ignored
+    async fn issue_update(&self, request: &Request) -> reqwest::Result<ControlFlow<Response, reqwest::Error>> {
+        let endpoint = match request.endpoint {
             Endpoint::Update => &self.update_endpoint,
             Endpoint::Gsp => &self.graph_store_endpoint,
         };
 
-        let req = match operation.method {
+        let req = match request.method {
             Method::Post => self.client.post(endpoint.clone()),
             Method::Put => self.client.put(endpoint.clone()),
             Method::Delete => self.client.delete(endpoint.clone()),
         };
 
+        let _permit = self.request_permit().await;
         let resp = req
-            .headers((&operation.headers).try_into().unwrap())
-            .query(&operation.query_params.clone())
-            .body(operation.body.clone())
+            .headers((&request.headers).try_into().unwrap())
+            .query(&request.query_params.clone())
+            .body(request.body.clone())
             .send()
             .await;
 
         match resp {
-            Ok(resp) => {
-                resp.error_for_status()?;
-                Ok(ControlFlow::Break(()))
-            },
-            Err(_) if self.behav == WorkerBehaviour::IgnoreConnectionError => Ok(ControlFlow::Continue(())),
+            Ok(resp) => Ok(ControlFlow::Break(resp)),
+            Err(e) if self.behav == WorkerBehaviour::IgnoreConnectionError => Ok(ControlFlow::Continue(e)),
             Err(e) => Err(e),
         }
     }
 
-    pub async fn execute(&self) -> Result<(), WorkerError> {
-        for (id, update) in self.queries.iter().enumerate() {
-            loop {
-                match self.issue_update(update).await {
-                    Ok(ControlFlow::Continue(())) => continue,
-                    Ok(ControlFlow::Break(())) => break Ok(()),
-                    Err(err) => {
+    /// Reads the current state, retrying until the server is reachable
+    async fn await_current_state(&self, id: usize, validate: &Validate) -> Result<DbState, WorkerError> {
+        loop {
+            match self.read_current_state(validate).await {
+                Ok(ControlFlow::Continue(())) => continue,
+                Ok(ControlFlow::Break(state)) => break Ok(state),
+                Err(err) => break Err(WorkerError::UpdateVerifyFailed { update_id: id, err }),
+            }
+        }
+    }
+
+    /// Called when the connection was lost while issuing an update, i.e. it is unknown whether the server applied it.
+    /// Blindly issuing the update again would be wrong for non-idempotent updates, so the current state decides instead.
+    /// It is only read after the server was restarted, as a server that is still running may still apply the update.
+    ///
+    /// Returns `Break` if the update was applied and `Continue` if it was not applied and must be issued again.
+    async fn resolve_unknown_outcome(
+        &self,
+        id: usize,
+        validate: &Validate,
+        pre_state: &DbState,
+    ) -> Result<ControlFlow<()>, WorkerError> {
+        let actual = self.await_current_state(id, validate).await?;
+
+        if actual == validate.expected {
+            Ok(ControlFlow::Break(()))
+        } else if actual == *pre_state {
+            Ok(ControlFlow::Continue(()))
+        } else {
+            Err(WorkerError::PartiallyApplied {
+                update_id: id,
+                verbose_info: if self.verbose {
+                    Some(TransitionVerboseInfo {
+                        query: validate.source.to_string(),
+                        pre_state: pre_state.to_string(),
+                        post_state: validate.expected.to_string(),
+                        actual: actual.to_string(),
+                    })
+                } else {
+                    None
+                },
+            })
+        }
+    }
+
+    /// Issues a single request, resolving the outcome if the connection is lost.
+    /// The request succeeds if the server responds with the status the request expects.
+    async fn issue_step(
+        &self,
+        id: usize,
+        request: &Request,
+        validate: Option<&Validate>,
+        pre_state: Option<&DbState>,
+    ) -> Result<(), WorkerError> {
+        let verbose_info = || {
+            if self.verbose {
+                Some(UpdateFailedVerboseInfo { query: format!("{request:?}") })
+            } else {
+                None
+            }
+        };
+
+        loop {
+            let completed_kills = self.durability.as_ref().map(WorkerDurabilityHandle::completed_kills);
+
+            match self.issue_update(request).await {
+                Ok(ControlFlow::Continue(err)) => {
+                    let (durability, completed_kills) = self
+                        .durability
+                        .as_ref()
+                        .zip(completed_kills)
+                        .expect("connection errors are only ignored in durability mode");
+
+                    // without a kill the server may still apply the update, so its outcome cannot be resolved
+                    if !durability.wait_for_kill_cycle(completed_kills).await {
                         break Err(WorkerError::UpdateFailed {
                             update_id: id,
-                            err,
-                            verbose_info: if self.verbose {
-                                Some(UpdateFailedVerboseInfo { query: format!("{update:?}") })
-                            } else {
-                                None
-                            },
-                        })
-                    },
-                }
-            }?;
-
-            loop {
-                match self.read_current_state(&update).await {
-                    Ok(ControlFlow::Continue(())) => continue,
-                    Ok(ControlFlow::Break(actual_state)) if actual_state == update.validate.expected => break Ok(()),
-                    Ok(ControlFlow::Break(actual_state)) => {
-                        break Err(WorkerError::InvalidState {
+                            err: err.into(),
+                            verbose_info: verbose_info(),
+                        });
+                    }
+
+                    let (validate, pre_state) = validate
+                        .zip(pre_state)
+                        .expect("steps without validation are rejected when loading the operations in durability mode");
+
+                    match self.resolve_unknown_outcome(id, validate, pre_state).await? {
+                        ControlFlow::Continue(()) => continue,
+                        ControlFlow::Break(()) => break Ok(()),
+                    }
+                },
+                Ok(ControlFlow::Break(resp)) if request.expect.matches(resp.status()) => break Ok(()),
+                Ok(ControlFlow::Break(resp)) if !request.expect.is_success() && resp.status().is_success() => {
+                    break Err(WorkerError::UnexpectedSuccess { update_id: id, verbose_info: verbose_info() })
+                },
+                Ok(ControlFlow::Break(resp)) => {
+                    let err = match resp.error_for_status() {
+                        Ok(resp) => UpdateError::UnexpectedStatus(resp.status()),
+                        Err(err) => err.into(),
+                    };
+
+                    break Err(WorkerError::UpdateFailed { update_id: id, err, verbose_info: verbose_info() });
+                },
+                Err(err) => {
+                    break Err(WorkerError::UpdateFailed {
+                        update_id: id,
+                        err: err.into(),
+                        verbose_info: verbose_info(),
+                    })
+                },
+            }
+        }
+    }
+
+    /// Checks that the validation query returns the expected state
+    async fn check_state(&self, id: usize, validate: &Validate) -> Result<(), WorkerError> {
+        loop {
+            match self.read_current_state(validate).await {
+                Ok(ControlFlow::Continue(())) => continue,
+                Ok(ControlFlow::Break(actual_state)) if actual_state == validate.expected => break Ok(()),
+                Ok(ControlFlow::Break(actual_state)) => {
+                    break Err(WorkerError::InvalidState {
+                        update_id: id,
+                        verbose_info: if self.verbose {
+                            Some(InvalidStateVerboseInfo {
+                                query: validate.source.to_string(),
+                                expected: validate.expected.to_string(),
+                                actual: actual_state.to_string(),
+                            })
+                        } else {
+                            None
+                        },
+                    })
+                },
+                Err(err) => break Err(WorkerError::UpdateVerifyFailed { update_id: id, err }),
+            }
+        }
+    }
+
+    /// Checks that a rejected request left the state before it unchanged
+    async fn check_rolled_back(
+        &self,
+        id: usize,
+        request: &Request,
+        validate: &Validate,
+        pre_state: &DbState,
+    ) -> Result<(), WorkerError> {
+        let actual = self.await_current_state(id, validate).await?;
+
+        if actual != *pre_state {
+            return Err(WorkerError::RollbackViolation {
+                update_id: id,
+                verbose_info: if self.verbose {
+                    Some(RollbackVerboseInfo {
+                        request: format!("{request:?}"),
+                        query: validate.source.to_string(),
+                        pre_state: pre_state.to_string(),
+                        actual: actual.to_string(),
+                    })
+                } else {
+                    None
+                },
+            });
+        }
+
+        // the state before the step only equals the expected state for sure if the step before validated the same data
+        if actual != validate.expected {
+            return Err(WorkerError::InvalidState {
+                update_id: id,
+                verbose_info: if self.verbose {
+                    Some(InvalidStateVerboseInfo {
+                        query: validate.source.to_string(),
+                        expected: validate.expected.to_string(),
+                        actual: actual.to_string(),
+                    })
+                } else {
+                    None
+                },
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Applies all operations in order, unless the worker is stopped before
+    pub async fn execute(&mut self, mut stop: tokio::sync::broadcast::Receiver<()>) -> Result<(), WorkerError> {
+        let res = tokio::select! {
+            res = self.apply_operations() => res,
+            _ = stop.recv() => Err(WorkerError::Stopped { update_id: self.op_durations.len() }),
+        };
+
+        // readers must not judge the states they observe against an operation that failed or was stopped
+        self.in_flight.send_replace(None);
+        res
+    }
+
+    async fn apply_operations(&mut self) -> Result<(), WorkerError> {
+        for (id, update) in self.queries.iter().enumerate() {
+            let op_start = Instant::now();
+
+            // the state before the update is needed by readers to check isolation
+            // and to resolve the outcome of updates interrupted by a server kill
+            let pre_state =
+                if self.in_flight.receiver_count() > 0 || self.behav == WorkerBehaviour::IgnoreConnectionError {
+                    let pre_state = self.await_current_state(id, &update.validate).await?;
+
+                    // readers cannot judge the states of non-atomic operations whose intermediate states are unknown
+                    let in_flight = update.intermediate_states().map(|intermediate_states| {
+                        Arc::new(InFlightOperation {
                             update_id: id,
-                            verbose_info: if self.verbose {
-                                Some(InvalidStateVerboseInfo {
-                                    query: update.validate.query.clone(),
-                                    expected: update.validate.expected.clone(),
-                                    actual: actual_state,
-                                })
-                            } else {
-                                None
-                            },
+                            source: update.validate.source.clone(),
+                            pre_state: pre_state.clone(),
+                            post_state: update.validate.expected.clone(),
+                            intermediate_states,
                         })
+                    });
+
+                    self.in_flight.send_replace(in_flight);
+
+                    Some(pre_state)
+                } else {
+                    None
+                };
+
+            let steps = update.steps();
+
+            if let Some(durability) = &self.durability {
+                let post_states = steps
+                    .iter()
+                    .filter_map(|(_, validate)| *validate)
+                    .map(|validate| (validate.source.clone(), validate.expected.clone()))
+                    .collect();
+
+                durability.issued(id, post_states);
+            }
+
+            self.invariants.issued(&update.invariant_deltas);
+
+            // the latest state that is known for sure, along with where it was read from
+            let mut known_state = pre_state.map(|pre_state| (&update.validate.source, pre_state));
+
+            // steps without a validation are recorded in the history together with the next validated step
+            let mut write_invoked = None;
+
+            for (step, (request, validate)) in steps.iter().enumerate() {
+                // the outcome of an interrupted step is resolved by comparing against the state before it,
+                // and a rejected step must leave the state before it unchanged
+                let step_pre_state = match validate {
+                    Some(validate)
+                        if self.behav == WorkerBehaviour::IgnoreConnectionError || !request.expect.is_success() =>
+                    {
+                        match known_state.take() {
+                            Some((source, state)) if *source == validate.source => Some(state),
+                            _ => Some(self.await_current_state(id, validate).await?),
+                        }
                     },
-                    Err(err) => break Err(WorkerError::UpdateVerifyFailed { update_id: id, err }),
+                    _ => None,
+                };
+
+                let invoked = self
+                    .history
+                    .as_ref()
+                    .map(|history| *write_invoked.get_or_insert_with(|| history.now()));
+                let res = self.issue_step(id, request, *validate, step_pre_state.as_ref()).await;
+
+                if let Some((history, invoked)) = self.history.as_ref().zip(invoked) {
+                    let write = match (&res, validate) {
+                        (Ok(()), Some(validate)) => Some((*validate, Some(history.now()))),
+                        (Ok(()), None) => None,
+                        // it is unknown whether a failed step took effect
+                        (Err(_), validate) => Some((validate.unwrap_or(&update.validate), None)),
+                    };
+
+                    if let Some((validate, completed)) = write {
+                        history.record(
+                            EventKind::Write,
+                            &validate.source,
+                            &validate.expected,
+                            invoked,
+                            completed,
+                        );
+                        write_invoked = None;
+                    }
+                }
+
+                res?;
+
+                if step == steps.len() - 1 {
+                    if let Some(durability) = &self.durability {
+                        durability
+                            .acknowledged(id, &update.validate.source, &update.validate.expected)
+                            .await;
+                    }
+
+                    self.invariants.acknowledged(&update.invariant_deltas);
                 }
-            }?;
+
+                if let Some(validate) = validate {
+                    match step_pre_state.filter(|_| !request.expect.is_success()) {
+                        Some(pre_state) => self.check_rolled_back(id, request, validate, &pre_state).await?,
+                        None => self.check_state(id, validate).await?,
+                    }
+
+                    known_state = Some((&validate.source, validate.expected.clone()));
+                }
+            }
+
+            // the operation completed, so readers must not judge the states they observe against it anymore
+            self.in_flight.send_replace(None);
+
+            self.op_durations.push(op_start.elapsed());
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, StubResponse};
+
+    const REQUEST: &str = r#""endpoint": "UPDATE",
+        "query_params": {},
+        "headers": { "Content-type": "application/sparql-update" },
+        "method": "POST",
+        "body": "INSERT DATA { <http://example.org/s> <http://example.org/p> <http://example.org/o> }""#;
+
+    const VALIDATE: &str = r#"{
+        "query": "CONSTRUCT { <http://example.org/s> ?p ?o } WHERE { <http://example.org/s> ?p ?o }",
+        "expected": "<http://example.org/s> <http://example.org/p> <http://example.org/o> .\n"
+    }"#;
+
+    fn config(query_endpoint: Url, update_endpoint: Url, behav: WorkerBehaviour) -> UpdateWorkerConfig {
+        UpdateWorkerConfig {
+            query_endpoint,
+            update_endpoint: update_endpoint.clone(),
+            graph_store_endpoint: update_endpoint,
+            verbose: false,
+            behav,
+            invariants: InvariantTracker::new(Vec::new()),
+        }
+    }
+
+    /// Loads an update worker with a single operation
+    fn load_worker(name: &str, operation: &str, config: UpdateWorkerConfig) -> anyhow::Result<UpdateWorker> {
+        let dir = std::env::temp_dir().join(format!("sparql-transactional-test-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("op_0.json"), operation).unwrap();
+
+        let worker = UpdateWorker::new(&dir, config, None, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+        worker
+    }
+
+    /// An operation of two steps, whose validate blocks are given as JSON (or `null`)
+    fn two_steps(first_validate: &str, last_validate: &str) -> String {
+        format!(
+            r#"{{
+                "steps": [{{ {REQUEST}, "validate": {first_validate} }}, {{ {REQUEST}, "validate": {last_validate} }}],
+                "validate": {VALIDATE}
+            }}"#
+        )
+        .replace(r#", "validate": null"#, "")
+    }
+
+    #[test]
+    fn step_validations_are_checked_on_load() {
+        let url = Url::parse("http://localhost/").unwrap();
+        let load = |operation: &str, behav| {
+            load_worker("steps", operation, config(url.clone(), url.clone(), behav)).map(drop)
+        };
+
+        assert!(load(&two_steps(VALIDATE, "null"), WorkerBehaviour::IgnoreConnectionError).is_ok());
+
+        // the last step is validated by the operation
+        let err = load(&two_steps(VALIDATE, VALIDATE), WorkerBehaviour::ReportConnectionError).unwrap_err();
+        assert!(err.to_string().contains("must not have a validate block"), "{err}");
+
+        // an interrupted step can only be resolved in durability mode if its state is known
+        assert!(load(&two_steps("null", "null"), WorkerBehaviour::ReportConnectionError).is_ok());
+        let err = load(&two_steps("null", "null"), WorkerBehaviour::IgnoreConnectionError).unwrap_err();
+        assert!(err.to_string().contains("needs a validate block in durability mode"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn failed_operation_is_not_published_anymore() {
+        // the state before the update can be read, but the update itself fails
+        let query_endpoint = test_server::serve(|_| StubResponse {
+            status: 200,
+            content_type: "application/n-triples",
+            body: String::new(),
+        })
+        .await;
+
+        let config = config(
+            query_endpoint,
+            test_server::unreachable_url().await,
+            WorkerBehaviour::ReportConnectionError,
+        );
+        let operation = format!(r#"{{ {REQUEST}, "validate": {VALIDATE} }}"#);
+        let mut worker = load_worker("in-flight", &operation, config).unwrap();
+
+        let in_flight = worker.subscribe_in_flight();
+        let (_stop_tx, stop) = tokio::sync::broadcast::channel(1);
+
+        let res = worker.execute(stop).await;
+
+        assert!(matches!(res, Err(WorkerError::UpdateFailed { update_id: 0, .. })));
+        assert!(in_flight.borrow().is_none());
+    }
+}